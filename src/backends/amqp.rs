@@ -0,0 +1,377 @@
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use amq_protocol::frame::{AMQPFrame, WriteContext, gen_frame, parse_frame};
+use amq_protocol::protocol::{AMQPClass, connection};
+use anyhow::{anyhow, bail};
+use clap::Args;
+use futures_util::{Stream, StreamExt};
+use lapin::message::Delivery;
+use lapin::options::{BasicConsumeOptions, BasicPublishOptions, QueueBindOptions, QueueDeclareOptions};
+use lapin::tcp::AMQPUriTcpExt;
+use lapin::types::{AMQPValue, FieldTable, ShortString};
+use lapin::uri::AMQPUri;
+use lapin::{BasicProperties, Channel, Connection, ConnectionProperties};
+
+use crate::cli::{NoCommands, NoOptions, parse_duration};
+use crate::mqtrait::{Frame, MessageQueue, TopicPublisher};
+use crate::utils::format_info;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// RabbitMQ pseudo-queue for replies without declaring a queue
+const DIRECT_REPLY_TO: &str = "amq.rabbitmq.reply-to";
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "AMQP Options")]
+pub struct AmqpOptions {
+    #[arg(global = true, long, help = "exchange to publish to and bind subscriptions to, empty for the default exchange (sub then consumes the queue named by channel)", default_value = "amq.topic")]
+    exchange: String,
+    #[arg(global = true, long, help = "req reply timeout, also limits reading server properties for info", default_value = "10s", value_parser = parse_duration)]
+    timeout: Duration,
+}
+
+fn value_to_string(value: &AMQPValue) -> String {
+    match value {
+        AMQPValue::Boolean(b) => b.to_string(),
+        AMQPValue::ShortShortInt(n) => n.to_string(),
+        AMQPValue::ShortShortUInt(n) => n.to_string(),
+        AMQPValue::ShortInt(n) => n.to_string(),
+        AMQPValue::ShortUInt(n) => n.to_string(),
+        AMQPValue::LongInt(n) => n.to_string(),
+        AMQPValue::LongUInt(n) => n.to_string(),
+        AMQPValue::LongLongInt(n) => n.to_string(),
+        AMQPValue::Float(n) => n.to_string(),
+        AMQPValue::Double(n) => n.to_string(),
+        AMQPValue::ShortString(s) => s.to_string(),
+        AMQPValue::LongString(s) => s.to_string(),
+        AMQPValue::Timestamp(t) => t.to_string(),
+        AMQPValue::Void => String::new(),
+        value => format!("{:?}", value),
+    }
+}
+
+/// Standard AMQP properties are set from headers with the same name (case-insensitive),
+/// everything else goes to application headers.
+fn properties_from_headers(headers: &[(String, String)]) -> anyhow::Result<BasicProperties> {
+    let mut properties = BasicProperties::default();
+    let mut table = FieldTable::default();
+    for (key, value) in headers {
+        let short = || ShortString::from(value.as_str());
+        properties = match &*key.to_ascii_lowercase() {
+            "content-type" => properties.with_content_type(short()),
+            "content-encoding" => properties.with_content_encoding(short()),
+            "correlation-id" => properties.with_correlation_id(short()),
+            "reply-to" => properties.with_reply_to(short()),
+            "message-id" => properties.with_message_id(short()),
+            "type" => properties.with_type(short()),
+            "app-id" => properties.with_app_id(short()),
+            "user-id" => properties.with_user_id(short()),
+            "expiration" => properties.with_expiration(short()),
+            "priority" => properties.with_priority(value.parse().map_err(|_| anyhow!("invalid priority: {}", value))?),
+            "delivery-mode" => properties.with_delivery_mode(value.parse().map_err(|_| anyhow!("invalid delivery mode: {}", value))?),
+            "timestamp" => properties.with_timestamp(value.parse().map_err(|_| anyhow!("invalid timestamp: {}", value))?),
+            _ => {
+                table.insert(key.as_str().into(), AMQPValue::LongString(value.as_str().into()));
+                properties
+            }
+        };
+    }
+    if !table.inner().is_empty() {
+        properties = properties.with_headers(table);
+    }
+    Ok(properties)
+}
+
+fn delivery_to_frame(delivery: &Delivery) -> Frame {
+    let mut frame = Frame {
+        topic: delivery.routing_key.to_string(),
+        headers: Default::default(),
+        payload: delivery.data.clone(),
+    };
+    let mut insert = |key: &str, value: String| {
+        frame.headers.entry(key.to_string()).or_default().push(value);
+    };
+    insert("Exchange", delivery.exchange.to_string());
+    if delivery.redelivered {
+        insert("Redelivered", "true".to_string());
+    }
+
+    let properties = &delivery.properties;
+    let strings = [
+        ("Content-Type", properties.content_type()),
+        ("Content-Encoding", properties.content_encoding()),
+        ("Correlation-Id", properties.correlation_id()),
+        ("Reply-To", properties.reply_to()),
+        ("Message-Id", properties.message_id()),
+        ("Type", properties.kind()),
+        ("App-Id", properties.app_id()),
+        ("User-Id", properties.user_id()),
+        ("Expiration", properties.expiration()),
+    ];
+    for (key, value) in strings {
+        if let Some(value) = value {
+            insert(key, value.to_string());
+        }
+    }
+    if let Some(priority) = properties.priority() {
+        insert("Priority", priority.to_string());
+    }
+    if let Some(delivery_mode) = properties.delivery_mode() {
+        insert("Delivery-Mode", delivery_mode.to_string());
+    }
+    if let Some(timestamp) = properties.timestamp() {
+        insert("Timestamp", timestamp.to_string());
+    }
+    if let Some(headers) = properties.headers() {
+        for (key, value) in headers.inner() {
+            insert(key.as_str(), value_to_string(value));
+        }
+    }
+    frame
+}
+
+/// Reads a frame: type (1), channel (2) and payload size (4), followed by payload and end marker.
+fn read_frame(stream: &mut impl Read) -> anyhow::Result<AMQPFrame> {
+    let mut data = vec![0; 7];
+    stream.read_exact(&mut data)?;
+    let size = u32::from_be_bytes([data[3], data[4], data[5], data[6]]) as usize;
+    data.resize(7 + size + 1, 0);
+    stream.read_exact(&mut data[7..])?;
+    match parse_frame(data.as_slice()) {
+        Ok((_, frame)) => Ok(frame),
+        Err(err) => bail!("invalid frame: {:?}", err),
+    }
+}
+
+/// lapin doesn't expose server properties from `connection.start`, so `info` reads them
+/// from a separate connection that is closed right after the server greeting.
+fn server_properties(uri: &AMQPUri, timeout: Duration) -> anyhow::Result<FieldTable> {
+    let deadline = Instant::now() + timeout;
+    let remaining = || deadline.checked_duration_since(Instant::now())
+        .filter(|remaining| !remaining.is_zero())
+        .ok_or_else(|| anyhow!("timed out"));
+
+    let mut uri = uri.clone();
+    uri.query.connection_timeout = Some(timeout.as_millis() as u64);
+    let mut handshake = uri.connect();
+    let mut stream = loop {
+        match handshake {
+            Ok(stream) => break stream,
+            Err(mid) => {
+                remaining()?;
+                handshake = mid.into_mid_handshake_tls_stream()?.handshake();
+            }
+        }
+    };
+    while !stream.try_connect()? {
+        remaining()?;
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(remaining()?))?;
+    stream.set_write_timeout(Some(remaining()?))?;
+
+    stream.write_all(b"AMQP\x00\x00\x09\x01")?;
+    let properties = match read_frame(&mut stream)? {
+        AMQPFrame::Method(_, AMQPClass::Connection(connection::AMQPMethod::Start(start))) => start.server_properties,
+        frame => bail!("unexpected frame: {:?}", frame),
+    };
+
+    // the greeting is all that's needed, close instead of leaving the server waiting for start-ok
+    let close = connection::Close { reply_code: 200, reply_text: "OK".into(), class_id: 0, method_id: 0 };
+    let frame = AMQPFrame::Method(0, AMQPClass::Connection(connection::AMQPMethod::Close(close)));
+    let data = gen_frame(&frame)(WriteContext::from(Vec::new()))
+        .map_err(|err| anyhow!("failed to encode frame: {:?}", err))?.write;
+    stream.write_all(&data)?;
+    if let Err(err) = read_frame(&mut stream) {
+        log::debug!("no close-ok from server: {}", err);
+    }
+    Ok(properties)
+}
+
+struct AmqpMQ {
+    url: String,
+    uri: AMQPUri,
+    connection: Connection,
+    channel: Channel,
+    options: AmqpOptions,
+}
+
+impl MessageQueue for AmqpMQ {
+    type Options = AmqpOptions;
+    type Commands = NoCommands;
+    type PublishOptions = NoOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = TopicPublisher<'a, Self>;
+
+    async fn connect(addr: Option<&str>, options: &AmqpOptions) -> anyhow::Result<Self> {
+        let url = addr.unwrap_or("amqp://localhost:5672").to_owned();
+        let uri: AMQPUri = url.parse()
+            .map_err(|err| anyhow!("invalid url: {}", err))?;
+        let properties = ConnectionProperties::default().with_connection_name("mqcat".into());
+        let connection = tokio::time::timeout(TIMEOUT, Connection::connect_uri(uri.clone(), properties)).await
+            .map_err(|_| anyhow!("failed to connect: timed out"))?
+            .map_err(|err| anyhow!("failed to connect: {}", err))?;
+        let channel = connection.create_channel().await
+            .map_err(|err| anyhow!("failed to open channel: {}", err))?;
+        Ok(Self { url, uri, connection, channel, options: options.clone() })
+    }
+
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
+        let uri = self.uri.clone();
+        let timeout = self.options.timeout;
+        let server_properties = tokio::task::spawn_blocking(move || server_properties(&uri, timeout)).await?
+            .map_err(|err| anyhow!("failed to read server properties: {}", err))?;
+        let server_properties = server_properties.inner();
+        let get = |key: &str| server_properties.get(key).map(value_to_string).unwrap_or_else(|| "-".to_string());
+
+        let mut info = vec![
+            ("URL", self.url.clone()),
+            ("Server Product", get("product")),
+            ("Server Version", get("version")),
+            ("Platform", get("platform")),
+            ("Cluster Name", get("cluster_name")),
+        ];
+        if let Some(AMQPValue::FieldTable(capabilities)) = server_properties.get("capabilities") {
+            let enabled: Vec<_> = capabilities.inner().iter()
+                .filter(|(_, value)| matches!(value, AMQPValue::Boolean(true)))
+                .map(|(key, _)| key.as_str())
+                .collect();
+            info.push(("Capabilities", enabled.join(", ")));
+        }
+
+        let configuration = self.connection.configuration();
+        info.push(("Channel Max", configuration.channel_max().to_string()));
+        info.push(("Frame Max", configuration.frame_max().to_string()));
+        info.push(("Heartbeat", format!("{:?}", Duration::from_secs(configuration.heartbeat() as u64))));
+
+        Ok(format_info(&info, json))
+    }
+
+    async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        let properties = properties_from_headers(headers)?;
+        self.channel.basic_publish(&self.options.exchange, topic, BasicPublishOptions::default(), payload, properties).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        Ok(())
+    }
+
+    async fn publisher(&self, topic: &str, _options: &NoOptions) -> anyhow::Result<TopicPublisher<'_, Self>> {
+        Ok(TopicPublisher::new(self, topic))
+    }
+
+    fn subscribe(&self, topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        let topic = topic.to_owned();
+
+        async_stream::try_stream! {
+            let queue = if self.options.exchange.is_empty() {
+                // default exchange routes directly to queues, so consume the queue itself
+                topic.clone()
+            } else {
+                let declare_options = QueueDeclareOptions { exclusive: true, auto_delete: true, ..Default::default() };
+                let queue = self.channel.queue_declare("", declare_options, FieldTable::default()).await
+                    .map_err(|err| anyhow!("failed to declare queue: {}", err))?;
+                self.channel.queue_bind(queue.name().as_str(), &self.options.exchange, &topic, QueueBindOptions::default(), FieldTable::default()).await
+                    .map_err(|err| anyhow!("failed to bind queue: {}", err))?;
+                log::debug!("bound queue {} to {} with \"{}\"", queue.name(), self.options.exchange, topic);
+                queue.name().to_string()
+            };
+
+            let consume_options = BasicConsumeOptions { no_ack: true, ..Default::default() };
+            let mut consumer = self.channel.basic_consume(&queue, "", consume_options, FieldTable::default()).await
+                .map_err(|err| anyhow!("failed to consume: {}", err))?;
+            while let Some(delivery) = consumer.next().await {
+                let delivery = delivery.map_err(|err| anyhow!("failed to receive: {}", err))?;
+                yield delivery_to_frame(&delivery);
+            }
+            Err(anyhow!("connection closed"))?;
+        }
+    }
+
+    async fn request(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<Frame> {
+        // direct reply-to requires consuming in no-ack mode on the publishing channel before publishing
+        let consume_options = BasicConsumeOptions { no_ack: true, ..Default::default() };
+        let mut consumer = self.channel.basic_consume(DIRECT_REPLY_TO, "", consume_options, FieldTable::default()).await
+            .map_err(|err| anyhow!("failed to consume replies: {}", err))?;
+
+        let properties = properties_from_headers(headers)?.with_reply_to(DIRECT_REPLY_TO.into());
+        self.channel.basic_publish(&self.options.exchange, topic, BasicPublishOptions::default(), payload, properties).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+
+        let reply = tokio::time::timeout(self.options.timeout, consumer.next()).await
+            .map_err(|_| anyhow!("no reply within {:?}", self.options.timeout))?
+            .ok_or_else(|| anyhow!("connection closed"))?
+            .map_err(|err| anyhow!("failed to receive reply: {}", err))?;
+        let _ = self.channel.basic_cancel(consumer.tag().as_str(), Default::default()).await;
+        Ok(delivery_to_frame(&reply))
+    }
+
+    async fn command(_addr: Option<&str>, _options: &AmqpOptions, command: NoCommands) -> anyhow::Result<()> {
+        match command {}
+    }
+}
+
+pub async fn run(args: impl Iterator<Item = String>) {
+    crate::cli::run::<AmqpMQ>(args).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn properties() {
+        let headers = [
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("correlation-id".to_string(), "42".to_string()),
+            ("priority".to_string(), "5".to_string()),
+            ("lang".to_string(), "en".to_string()),
+        ];
+        let properties = properties_from_headers(&headers).unwrap();
+        assert_eq!(properties.content_type().as_ref().map(|s| s.as_str()), Some("application/json"));
+        assert_eq!(properties.correlation_id().as_ref().map(|s| s.as_str()), Some("42"));
+        assert_eq!(*properties.priority(), Some(5));
+        let table = properties.headers().as_ref().unwrap();
+        assert_eq!(table.inner().get("lang"), Some(&AMQPValue::LongString("en".into())));
+
+        assert!(properties_from_headers(&[("priority".to_string(), "high".to_string())]).is_err());
+    }
+
+    #[test]
+    fn server_greeting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri: AMQPUri = format!("amqp://{}", listener.local_addr().unwrap()).parse().unwrap();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0; 8];
+            stream.read_exact(&mut header).unwrap();
+            assert_eq!(&header, b"AMQP\x00\x00\x09\x01");
+
+            let mut properties = FieldTable::default();
+            properties.insert("product".into(), AMQPValue::LongString("RabbitMQ".into()));
+            let start = connection::Start {
+                version_major: 0,
+                version_minor: 9,
+                server_properties: properties,
+                mechanisms: "PLAIN".into(),
+                locales: "en_US".into(),
+            };
+            let frame = AMQPFrame::Method(0, AMQPClass::Connection(connection::AMQPMethod::Start(start)));
+            let data = gen_frame(&frame)(WriteContext::from(Vec::new())).unwrap().write;
+            stream.write_all(&data).unwrap();
+
+            let close = read_frame(&mut stream).unwrap();
+            let close_ok = AMQPFrame::Method(0, AMQPClass::Connection(connection::AMQPMethod::CloseOk(connection::CloseOk {})));
+            let data = gen_frame(&close_ok)(WriteContext::from(Vec::new())).unwrap().write;
+            stream.write_all(&data).unwrap();
+            close
+        });
+
+        let properties = server_properties(&uri, Duration::from_secs(5)).unwrap();
+        let close = handle.join().unwrap();
+        assert!(matches!(close, AMQPFrame::Method(0, AMQPClass::Connection(connection::AMQPMethod::Close(connection::Close { reply_code: 200, .. })))));
+        assert_eq!(properties.inner().get("product"), Some(&AMQPValue::LongString("RabbitMQ".into())));
+    }
+}
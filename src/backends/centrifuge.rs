@@ -1,125 +1,471 @@
-use std::time::Duration;
+pub(crate) mod api;
+mod fossil;
+mod jwt;
+mod session;
 
-use anyhow::anyhow;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail};
+use clap::{Args, Subcommand, ValueEnum};
 use futures_util::Stream;
-use tokio_centrifuge::client::Client;
-use tokio_centrifuge::config::Config;
+use tokio::time::Instant;
+use tokio_centrifuge::config::Protocol;
+use tokio_centrifuge::protocol::{
+    ClientInfo, Command, HistoryRequest, PresenceRequest, PresenceStatsRequest, Publication, PublishRequest,
+    PushData, Reply, RpcRequest, StreamPosition, SubRefreshRequest, SubscribeRequest,
+};
+
+use self::api::ApiClient;
+use self::session::{Event, Session, TokenFn};
+use crate::cli::{NoOptions, parse_duration, print_data};
+use crate::mqtrait::{ConnectionEvent, Frame, MessageQueue, TopicPublisher};
+use crate::utils::{broadcast_stream, format_info, format_table, info_to_json};
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Centrifuge Options")]
+pub struct CentrifugeOptions {
+    #[arg(global = true, long, help = "connection token (jwt)")]
+    token: Option<String>,
+    #[arg(global = true, long, help = "subscription token (jwt)")]
+    sub_token: Option<String>,
+    #[arg(global = true, long, help = "mint HS256 connection and subscription tokens locally with this secret", conflicts_with_all = ["token", "sub_token"])]
+    hmac_secret: Option<String>,
+    #[arg(global = true, long, help = "user id (`sub` claim) of minted tokens", default_value = "")]
+    jwt_sub: String,
+    #[arg(global = true, long, help = "lifetime (`exp` claim) of minted tokens", default_value = "1h", value_parser = parse_duration)]
+    jwt_exp: Duration,
+    #[arg(global = true, long, help = "server-side subscription (`channels` claim) of minted connection token")]
+    jwt_channel: Vec<String>,
+    #[arg(global = true, long, help = "connection info (`info` claim) of minted tokens, json", value_parser = parse_json)]
+    jwt_info: Option<serde_json::Value>,
+    #[arg(global = true, long, help = "server API key, publishing with headers (tags) goes through server API if set")]
+    api_key: Option<String>,
+    #[arg(global = true, long, help = "server API endpoint [default: /api on websocket host]")]
+    api_url: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Centrifuge Subscribe Options")]
+pub struct CentrifugeSubscribeOptions {
+    #[arg(long, help = "recover missed publications on resubscribe")]
+    recover: bool,
+    #[arg(long, help = "stream position to start from, as printed in Offset/Epoch headers", value_name = "OFFSET:EPOCH", value_parser = parse_position)]
+    since: Option<StreamPosition>,
+    #[arg(long, help = "print join/leave events of other clients")]
+    join_leave: bool,
+    #[arg(long, help = "delta compression of publications", value_enum)]
+    delta: Option<Delta>,
+    #[arg(long, help = "ask server to make subscription positioned, i.e. check for lost publications")]
+    positioned: bool,
+    #[arg(long, help = "ask server to make subscription recoverable, implied by --recover")]
+    recoverable: bool,
+    #[arg(long, help = "custom data sent with subscribe request, e.g. to subscribe proxy")]
+    sub_data: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Delta {
+    Fossil,
+}
 
-use crate::mqtrait::{Frame, MessageQueue};
-use crate::utils::format_table;
+#[derive(Subcommand, Debug)]
+pub enum CentrifugeCommands {
+    #[command(about = "print publications from channel history")]
+    History {
+        #[arg(help = "channel name")]
+        channel: String,
+        #[arg(long, help = "maximum number of publications (0 returns stream position only, -1 for no limit)", default_value = "-1", allow_hyphen_values = true)]
+        limit: i32,
+        #[arg(long, help = "stream position to start from, as printed in Offset/Epoch headers", value_name = "OFFSET:EPOCH", value_parser = parse_position)]
+        since: Option<StreamPosition>,
+        #[arg(long, help = "iterate from newest to oldest")]
+        reverse: bool,
+        #[arg(long, help = "decode the message by passing it through a given command")]
+        translate: Option<String>,
+    },
+
+    #[command(about = "list clients subscribed to a channel")]
+    Presence {
+        #[arg(help = "channel name")]
+        channel: String,
+        #[arg(long, help = "print presence as json")]
+        json: bool,
+    },
+
+    #[command(about = "show number of clients and users subscribed to a channel")]
+    PresenceStats {
+        #[arg(help = "channel name")]
+        channel: String,
+        #[arg(long, help = "print presence stats as json")]
+        json: bool,
+    },
+}
+
+fn parse_position(s: &str) -> Result<StreamPosition, String> {
+    let (offset, epoch) = s.split_once(':').ok_or("expected OFFSET:EPOCH")?;
+    let offset = offset.parse().map_err(|err| format!("invalid offset: {}", err))?;
+    Ok(StreamPosition { offset, epoch: epoch.to_owned() })
+}
+
+fn client_info_table(info: &mut Vec<(&str, String)>, client: &ClientInfo) {
+    info.push(("Client ID", client.client.clone()));
+    info.push(("User", client.user.clone()));
+    if !client.conn_info.is_empty() {
+        info.push(("Conn Info", String::from_utf8_lossy(&client.conn_info).into_owned()));
+    }
+    if !client.chan_info.is_empty() {
+        info.push(("Chan Info", String::from_utf8_lossy(&client.chan_info).into_owned()));
+    }
+}
+
+/// Clients are kept as objects, so repeated fields don't get split into separate arrays.
+fn presence_json(info: &[(&str, String)], clients: &[ClientInfo]) -> String {
+    // connection and channel info are usually json, anything else is kept as text
+    let info_value = |data: &[u8]| match data.is_empty() {
+        true => serde_json::Value::Null,
+        false => serde_json::from_slice(data)
+            .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(data).into_owned())),
+    };
+    let mut object = info_to_json(info);
+    let presence = clients.iter().map(|client| serde_json::json!({
+        "client": client.client,
+        "user": client.user,
+        "conn_info": info_value(&client.conn_info),
+        "chan_info": info_value(&client.chan_info),
+    })).collect();
+    object.insert("Presence".to_string(), serde_json::Value::Array(presence));
+    let mut json = serde_json::to_string_pretty(&object).unwrap_or_default();
+    json.push('\n');
+    json
+}
+
+// join/leave events are printed as frames, with connection info as payload
+fn join_leave_to_frame(channel: &str, event: &str, client: Option<ClientInfo>) -> Frame {
+    let client = client.unwrap_or_default();
+    let mut frame = Frame {
+        topic: channel.to_owned(),
+        headers: Default::default(),
+        payload: client.conn_info,
+    };
+    frame.headers.insert("Event".to_string(), vec![event.to_owned()]);
+    frame.headers.insert("Client-ID".to_string(), vec![client.client]);
+    frame.headers.insert("User".to_string(), vec![client.user]);
+    frame
+}
+
+// reconstructs full payloads of delta-compressed subscription from previous ones
+struct DeltaDecoder {
+    json: bool,
+    previous: Vec<u8>,
+}
+
+impl DeltaDecoder {
+    fn apply(&mut self, publication: &mut Publication) -> anyhow::Result<()> {
+        // with json protocol, both full payloads and deltas are sent as json strings
+        let data = if self.json {
+            serde_json::from_slice::<String>(&publication.data)
+                .map_err(|err| anyhow!("invalid delta publication: {}", err))?
+                .into_bytes()
+        } else {
+            std::mem::take(&mut publication.data)
+        };
+        if publication.delta {
+            let payload = fossil::apply(&self.previous, &data)
+                .map_err(|err| anyhow!("failed to apply delta: {}", err))?;
+            log::debug!("applied delta of {} bytes, full payload {} bytes", data.len(), payload.len());
+            publication.data = payload;
+            publication.delta = false;
+        } else {
+            publication.data = data;
+        }
+        self.previous = publication.data.clone();
+        Ok(())
+    }
+}
+
+fn publication_to_frame(channel: &str, epoch: &str, publication: Publication) -> Frame {
+    let mut frame = Frame {
+        topic: if publication.channel.is_empty() { channel.to_owned() } else { publication.channel },
+        headers: publication.tags.into_iter().map(|(k, v)| (k, vec![v])).collect(),
+        payload: publication.data,
+    };
+    // offsets are only assigned in channels with history
+    if publication.offset > 0 {
+        frame.headers.insert("Offset".to_string(), vec![publication.offset.to_string()]);
+        frame.headers.insert("Epoch".to_string(), vec![epoch.to_owned()]);
+    }
+    frame
+}
+
+fn parse_json(s: &str) -> Result<serde_json::Value, String> {
+    serde_json::from_str(s).map_err(|err| err.to_string())
+}
+
+impl CentrifugeOptions {
+    fn claims(&self) -> serde_json::Map<String, serde_json::Value> {
+        let exp = (SystemTime::now() + self.jwt_exp).duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut claims = serde_json::Map::new();
+        claims.insert("sub".to_string(), self.jwt_sub.clone().into());
+        claims.insert("exp".to_string(), exp.as_secs().into());
+        if let Some(info) = &self.jwt_info {
+            claims.insert("info".to_string(), info.clone());
+        }
+        claims
+    }
+
+    fn connection_token(&self) -> anyhow::Result<Option<String>> {
+        if let Some(secret) = &self.hmac_secret {
+            let mut claims = self.claims();
+            if !self.jwt_channel.is_empty() {
+                claims.insert("channels".to_string(), self.jwt_channel.clone().into());
+            }
+            return Ok(Some(jwt::encode_hs256(&claims.into(), secret.as_bytes())));
+        }
+        Ok(self.token.clone())
+    }
+
+    fn subscription_token(&self, channel: &str) -> Option<String> {
+        if let Some(secret) = &self.hmac_secret {
+            let mut claims = self.claims();
+            claims.insert("channel".to_string(), channel.into());
+            return Some(jwt::encode_hs256(&claims.into(), secret.as_bytes()));
+        }
+        self.sub_token.clone()
+    }
+}
 
 struct CentrifugeMQ<const JSON: bool> {
     url: String,
-    client: Client,
+    session: Session,
+    api: Option<ApiClient>,
+    options: CentrifugeOptions,
+}
+
+impl<const JSON: bool> CentrifugeMQ<JSON> {
+    // returns when the refreshed subscription token should be refreshed again
+    async fn refresh_subscription(&self, channel: &str) -> Option<Instant> {
+        if self.options.hmac_secret.is_none() {
+            log::warn!("subscription token expires soon and can't be refreshed");
+            return None;
+        }
+        let reply = self.session.call(Command::SubRefresh(SubRefreshRequest {
+            channel: channel.to_owned(),
+            token: self.options.subscription_token(channel).unwrap_or_default(),
+        })).await;
+        match reply {
+            Ok(Reply::SubRefresh(result)) => {
+                log::debug!("subscription token refreshed, expires={}, ttl={}", result.expires, result.ttl);
+                result.expires.then(|| session::refresh_deadline(result.ttl))
+            }
+            Ok(reply) => {
+                log::warn!("failed to refresh subscription token: {:?}", reply);
+                None
+            }
+            Err(err) => {
+                log::warn!("failed to refresh subscription token: {}", err);
+                None
+            }
+        }
+    }
 }
 
 impl<const JSON: bool> MessageQueue for CentrifugeMQ<JSON> {
-    async fn connect(addr: Option<&str>) -> anyhow::Result<Self> {
-        let (default_addr, config) = if JSON {
-            ("ws://localhost:8000/connection/websocket?format=json", Config::new().use_json())
+    type Options = CentrifugeOptions;
+    type Commands = CentrifugeCommands;
+    type PublishOptions = NoOptions;
+    type SubscribeOptions = CentrifugeSubscribeOptions;
+    type Publisher<'a> = TopicPublisher<'a, Self>;
+
+    async fn connect(addr: Option<&str>, options: &CentrifugeOptions) -> anyhow::Result<Self> {
+        let (default_addr, protocol) = if JSON {
+            ("ws://localhost:8000/connection/websocket?format=json", Protocol::Json)
         } else {
-            ("ws://localhost:8000/connection/websocket?format=protobuf", Config::new().use_protobuf())
+            ("ws://localhost:8000/connection/websocket?format=protobuf", Protocol::Protobuf)
         };
 
         let url = addr.unwrap_or(default_addr).to_owned();
 
-        let client = Client::new(
+        // static tokens are sent again on reconnect, but can't be refreshed
+        let token: TokenFn = {
+            let options = options.clone();
+            Arc::new(move |refresh| {
+                if refresh && options.hmac_secret.is_none() {
+                    return Ok(None);
+                }
+                options.connection_token()
+            })
+        };
+
+        let session = Session::connect(
             &url,
-            config.with_name(format!("mqcat {}", crate::version::get_version()))
-        );
+            protocol,
+            &format!("mqcat {}", crate::version::get_version()),
+            token,
+        ).await.map_err(|err| anyhow!("failed to connect: {}", err))?;
 
-        client.on_connecting(|e| {
-            log::debug!("connecting (code={}, reason={})", e.code, e.reason);
-        });
-        client.on_connected(|e| {
-            log::debug!("connected, client_id={}, version={}", e.client_id, e.version);
-        });
-        client.on_disconnected(|e| {
-            log::debug!("disconnected (code={}, reason={})", e.code, e.reason);
+        let api = options.api_key.as_ref().map(|key| {
+            let api_url = options.api_url.clone().unwrap_or_else(|| api::api_url_from_ws(&url));
+            ApiClient::new(&api_url, Some(key))
         });
-        client.on_error(|err| {
-            log::error!("error: {:?}", err);
-        });
-
-        client.connect().await.map_err(|_| anyhow::Error::msg("failed to connect"))?;
 
-        Ok(Self { url, client })
+        Ok(Self { url, session, api, options: options.clone() })
     }
 
-    async fn info(&self) -> anyhow::Result<String> {
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
         let mut info = vec![];
         info.push(("URL", self.url.clone()));
         info.push(("Encoding", if JSON { "JSON" } else { "Protobuf" }.to_owned()));
 
-        let server_info = self.client.server_info();
-        info.push(("Client ID", server_info.client_id.to_string()));
+        let server_info = self.session.info();
+        info.push(("Client ID", server_info.client.to_string()));
 
-        info.push(("Server Version", server_info.server_version));
-        info.push(("Ping Interval", format!("{:?}", Duration::from_secs(server_info.server_ping as u64))));
-        info.push(("Pong Required", server_info.send_pong.to_string()));
-        info.push(("Token Expires", server_info.token_expires.to_string()));
-        if server_info.token_expires {
-            info.push(("Token TTL", format!("{:?}", Duration::from_secs(server_info.token_ttl as u64))));
+        info.push(("Server Version", server_info.version));
+        info.push(("Ping Interval", format!("{:?}", Duration::from_secs(server_info.ping as u64))));
+        info.push(("Pong Required", server_info.pong.to_string()));
+        info.push(("Token Expires", server_info.expires.to_string()));
+        if server_info.expires {
+            info.push(("Token TTL", format!("{:?}", Duration::from_secs(server_info.ttl as u64))));
+        }
+        if let Some(api) = &self.api {
+            info.push(("API URL", api.url().to_owned()));
         }
 
-        Ok(format_table(&info))
+        Ok(format_info(&info, json))
     }
 
     async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
         if headers.len() > 0 {
-            log::warn!("setting headers is not supported by centrifuge");
+            // tags can only be set by the server, so this goes through server API instead of the connection
+            if let Some(api) = &self.api {
+                let tags: serde_json::Map<_, _> = headers.iter()
+                    .map(|(k, v)| (k.clone(), v.clone().into()))
+                    .collect();
+                let mut params = serde_json::json!({ "channel": topic, "tags": tags });
+                api::data_params(&mut params, payload);
+                api.call("publish", params).await
+                    .map_err(|err| anyhow!("failed to publish: {}", err))?;
+                return Ok(());
+            }
+            log::warn!("setting headers requires server API, use --api-key");
         }
-        self.client.publish(topic, payload.to_vec()).await
-            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        self.session.call(Command::Publish(PublishRequest {
+            channel: topic.to_owned(),
+            data: payload.to_vec(),
+        })).await.map_err(|err| anyhow!("failed to publish: {}", err))?;
         Ok(())
     }
 
-    fn subscribe(&self, topic: &str) -> impl Stream<Item = anyhow::Result<Frame>> {
-        let sub = self.client.new_subscription(topic);
-        let (recv_tx, mut recv_rx) = tokio::sync::mpsc::channel(64);
-        let (unsub_tx, mut unsub_rx) = tokio::sync::mpsc::channel(1);
+    async fn publisher(&self, topic: &str, _options: &NoOptions) -> anyhow::Result<TopicPublisher<'_, Self>> {
+        Ok(TopicPublisher::new(self, topic))
+    }
 
-        sub.on_subscribed(|e| {
-            log::debug!("subscribed to {}", e.channel);
-        });
-        let unsub_tx_ = unsub_tx.clone();
-        sub.on_unsubscribed(move |e| {
-            log::debug!("unsubscribed from {} (code={}, reason={})", e.channel, e.code, e.reason);
-            let _ = unsub_tx_.try_send((e.code, e.reason.to_owned()));
-        });
-        sub.on_subscribing(|e| {
-            log::debug!("subscribing to {} (code={}, reason={})", e.channel, e.code, e.reason);
-        });
-        sub.on_publication(move |data| {
-            let frame = Frame {
-                topic: data.channel,
-                headers: data.tags.into_iter().map(|(k, v)| (k, vec![v])).collect(),
-                payload: data.data,
-            };
-            if let Err(err) = recv_tx.try_send(frame) {
-                let _ = unsub_tx.try_send((0, format!("{}", err)));
-            }
-        });
+    fn subscribe(&self, topic: &str, options: &CentrifugeSubscribeOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        let mut events = self.session.listen(topic);
 
-        sub.subscribe();
+        // with --since, recovery starts from the given position instead of now
+        let recover = options.recover || options.since.is_some();
+        let mut position = options.since.clone();
 
         async_stream::try_stream! {
-            loop {
-                tokio::select! {
-                    Some(mut frame) = recv_rx.recv() => {
-                        if frame.topic.is_empty() {
-                            frame.topic = topic.to_owned();
-                        }
-                        yield frame;
+            // subscribe again after every reconnect
+            'subscribe: loop {
+                let reply = self.session.call(Command::Subscribe(SubscribeRequest {
+                    channel: topic.to_owned(),
+                    token: self.options.subscription_token(topic).unwrap_or_default(),
+                    recover: recover && position.is_some(),
+                    epoch: position.as_ref().map(|p| p.epoch.clone()).unwrap_or_default(),
+                    offset: position.as_ref().map(|p| p.offset).unwrap_or_default(),
+                    recoverable: recover || options.recoverable,
+                    join_leave: options.join_leave,
+                    positioned: options.positioned,
+                    delta: match options.delta {
+                        Some(Delta::Fossil) => "fossil".to_string(),
+                        None => String::new(),
+                    },
+                    data: options.sub_data.clone().unwrap_or_default().into_bytes(),
+                })).await.map_err(|err| anyhow!("subscription failed: {}", err))?;
+                let Reply::Subscribe(result) = reply else {
+                    Err(anyhow!("subscription failed: unexpected reply {:?}", reply))?;
+                    unreachable!();
+                };
+                log::debug!("subscribed to {}, offset={}, epoch={}", topic, result.offset, result.epoch);
+                if !result.data.is_empty() {
+                    log::info!("subscribe data: {}", String::from_utf8_lossy(&result.data));
+                }
+
+                // server silently ignores options that aren't allowed for the channel
+                if options.delta.is_some() && !result.delta {
+                    log::warn!("delta compression is not enabled for {} (check allowed_delta_types)", topic);
+                }
+                if options.positioned && !result.positioned {
+                    log::warn!("subscription to {} is not positioned (check force_positioning or allow_positioning)", topic);
+                }
+                if (recover || options.recoverable) && !result.recoverable {
+                    log::warn!("subscription to {} is not recoverable (check force_recovery or allow_recovery)", topic);
+                }
+                let mut delta = result.delta.then(|| DeltaDecoder { json: JSON, previous: vec![] });
+
+                if result.was_recovering && !result.recovered {
+                    log::warn!("failed to recover all missed publications on {}", topic);
+                }
+                if result.recoverable {
+                    position = Some(StreamPosition { offset: result.offset, epoch: result.epoch.clone() });
+                }
+                let epoch = result.epoch;
+                for mut publication in result.publications {
+                    if let Some(delta) = &mut delta {
+                        delta.apply(&mut publication)?;
                     }
-                    Some((code, reason)) = unsub_rx.recv() => {
-                        break Err(anyhow!("subscription failed: {} {}", code, reason));
+                    if let Some(position) = &mut position {
+                        position.offset = position.offset.max(publication.offset);
                     }
-                    else => {
-                        break Err(anyhow!("subscription closed"));
+                    yield publication_to_frame(topic, &epoch, publication);
+                }
+
+                let mut refresh_at = result.expires.then(|| session::refresh_deadline(result.ttl));
+                loop {
+                    let event = tokio::select! {
+                        event = events.recv() => event,
+                        _ = session::sleep_until(refresh_at) => {
+                            refresh_at = self.refresh_subscription(topic).await;
+                            continue;
+                        }
+                    };
+                    match event {
+                        Some(Event::Push(PushData::Publication(mut publication))) => {
+                            if let Some(delta) = &mut delta {
+                                delta.apply(&mut publication)?;
+                            }
+                            if let Some(position) = &mut position {
+                                position.offset = position.offset.max(publication.offset);
+                            }
+                            yield publication_to_frame(topic, &epoch, publication);
+                        }
+                        Some(Event::Push(PushData::Unsubscribe(e))) => {
+                            log::debug!("unsubscribed from {} (code={}, reason={})", topic, e.code, e.reason);
+                            Err(anyhow!("subscription failed: {} {}", e.code, e.reason))?;
+                        }
+                        Some(Event::Push(PushData::Join(join))) => {
+                            yield join_leave_to_frame(topic, "join", join.info);
+                        }
+                        Some(Event::Push(PushData::Leave(leave))) => {
+                            yield join_leave_to_frame(topic, "leave", leave.info);
+                        }
+                        Some(Event::Push(_)) => {}
+                        Some(Event::Disconnected { code, reason, reconnect }) => {
+                            log::debug!("disconnected (code={}, reason={}, reconnect={})", code, reason, reconnect);
+                        }
+                        Some(Event::Connected) => {
+                            continue 'subscribe;
+                        }
+                        None => {
+                            Err(anyhow!("subscription closed"))?;
+                        }
                     }
                 }
-            }?;
+            }
         }
     }
 
@@ -127,16 +473,115 @@ impl<const JSON: bool> MessageQueue for CentrifugeMQ<JSON> {
         if headers.len() > 0 {
             log::warn!("setting headers is not supported by centrifuge");
         }
-        let res = self.client.rpc(topic, payload.to_vec()).await
-            .map_err(|err| anyhow!("failed to execute rpc: {}", err))?;
+        let reply = self.session.call(Command::Rpc(RpcRequest {
+            method: topic.to_owned(),
+            data: payload.to_vec(),
+        })).await.map_err(|err| anyhow!("failed to execute rpc: {}", err))?;
+        let Reply::Rpc(res) = reply else {
+            bail!("failed to execute rpc: unexpected reply {:?}", reply);
+        };
         Ok(Frame {
             topic: topic.to_owned(),
             headers: Default::default(),
-            payload: res,
+            payload: res.data,
         })
     }
+
+    async fn command(addr: Option<&str>, options: &CentrifugeOptions, command: CentrifugeCommands) -> anyhow::Result<()> {
+        match command {
+            CentrifugeCommands::History { channel, limit, since, reverse, translate } => {
+                let mq = Self::connect(addr, options).await?;
+                let reply = mq.session.call(Command::History(HistoryRequest {
+                    channel: channel.clone(),
+                    limit,
+                    since,
+                    reverse,
+                })).await.map_err(|err| anyhow!("history failed: {}", err))?;
+                let Reply::History(result) = reply else {
+                    bail!("history failed: unexpected reply {:?}", reply);
+                };
+
+                let count = result.publications.len();
+                for (idx, publication) in result.publications.into_iter().enumerate() {
+                    print_data(idx as u32 + 1, &publication_to_frame(&channel, &result.epoch, publication), &translate).await?;
+                }
+                log::info!("received {} publications, stream position {}:{}", count, result.offset, result.epoch);
+            }
+            CentrifugeCommands::Presence { channel, json } => {
+                let mq = Self::connect(addr, options).await?;
+                let reply = mq.session.call(Command::Presence(PresenceRequest { channel: channel.clone() }))
+                    .await.map_err(|err| anyhow!("presence failed: {}", err))?;
+                let Reply::Presence(result) = reply else {
+                    bail!("presence failed: unexpected reply {:?}", reply);
+                };
+
+                let mut clients: Vec<_> = result.presence.into_values().collect();
+                clients.sort_by(|a, b| (&a.user, &a.client).cmp(&(&b.user, &b.client)));
+
+                let mut info = vec![];
+                info.push(("Channel", channel));
+                info.push(("Clients", clients.len().to_string()));
+                let out = match json {
+                    true => presence_json(&info, &clients),
+                    false => {
+                        for client in &clients {
+                            info.push(("", String::new()));
+                            client_info_table(&mut info, client);
+                        }
+                        format_table(&info)
+                    }
+                };
+                std::io::stdout().write_all(out.as_bytes())?;
+                std::io::stdout().flush()?;
+            }
+            CentrifugeCommands::PresenceStats { channel, json } => {
+                let mq = Self::connect(addr, options).await?;
+                let reply = mq.session.call(Command::PresenceStats(PresenceStatsRequest { channel: channel.clone() }))
+                    .await.map_err(|err| anyhow!("presence stats failed: {}", err))?;
+                let Reply::PresenceStats(result) = reply else {
+                    bail!("presence stats failed: unexpected reply {:?}", reply);
+                };
+
+                let info = vec![
+                    ("Channel", channel),
+                    ("Clients", result.num_clients.to_string()),
+                    ("Users", result.num_users.to_string()),
+                ];
+                std::io::stdout().write_all(format_info(&info, json).as_bytes())?;
+                std::io::stdout().flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn connection_events(&self) -> impl Stream<Item = ConnectionEvent> {
+        broadcast_stream(self.session.connection_events())
+    }
 }
 
 pub async fn run<const JSON: bool>(args: impl Iterator<Item = String>) {
     crate::cli::run::<CentrifugeMQ<JSON>>(args).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presence_as_json() {
+        let clients = [
+            ClientInfo { user: "alice".to_string(), client: "c1".to_string(), conn_info: br#"{"name":"Alice"}"#.to_vec(), chan_info: vec![] },
+            ClientInfo { user: "bob".to_string(), client: "c2".to_string(), conn_info: vec![], chan_info: b"moderator".to_vec() },
+        ];
+        let info = [("Channel", "chat".to_string()), ("Clients", "2".to_string())];
+        let json: serde_json::Value = serde_json::from_str(&presence_json(&info, &clients)).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "Channel": "chat",
+            "Clients": "2",
+            "Presence": [
+                { "client": "c1", "user": "alice", "conn_info": { "name": "Alice" }, "chan_info": null },
+                { "client": "c2", "user": "bob", "conn_info": null, "chan_info": "moderator" },
+            ],
+        }));
+    }
+}
@@ -0,0 +1,89 @@
+//! Centrifugo server HTTP API (`POST /api/<method>` with JSON params), some things like
+//! publication tags can only be set from the server side.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::{Value, json};
+
+pub struct ApiClient {
+    url: String,
+    key: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl ApiClient {
+    pub fn new(url: &str, key: Option<&str>) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(Duration::from_secs(10)))
+            .build()
+            .into();
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            key: key.map(ToOwned::to_owned),
+            agent,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Call API method, returns `result` field of the response.
+    pub async fn call(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let url = format!("{}/{}", self.url, method);
+        let key = self.key.clone();
+        let agent = self.agent.clone();
+        log::debug!("calling {} with {}", url, params);
+
+        // ureq is blocking, and requests are rare enough to not bother with a separate client
+        let (status, body) = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+            let mut request = agent.post(&url);
+            if let Some(key) = key {
+                request = request.header("X-API-Key", key);
+            }
+            let mut response = request.send_json(&params)?;
+            Ok((response.status(), response.body_mut().read_to_string()?))
+        }).await??;
+
+        if !status.is_success() {
+            bail!("http status {}: {}", status.as_u16(), body.trim());
+        }
+        let mut response: Value = serde_json::from_str(&body)
+            .map_err(|err| anyhow!("invalid response: {}", err))?;
+        if let Some(error) = response.get("error") {
+            bail!("{} (code={})", error["message"].as_str().unwrap_or_default(), error["code"]);
+        }
+        Ok(response["result"].take())
+    }
+}
+
+/// Default API endpoint on the same host as websocket endpoint.
+pub fn api_url_from_ws(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("ws", url));
+    let scheme = if scheme == "wss" { "https" } else { "http" };
+    let host = rest.split(['/', '?']).next().unwrap_or_default();
+    format!("{}://{}/api", scheme, host)
+}
+
+/// JSON payloads are sent as is, anything else is base64-encoded.
+pub fn data_params(params: &mut Value, payload: &[u8]) {
+    match serde_json::from_slice::<Value>(payload) {
+        Ok(data) => params["data"] = data,
+        Err(_) => params["b64data"] = json!(STANDARD.encode(payload)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_url() {
+        assert_eq!(api_url_from_ws("ws://localhost:8000/connection/websocket?format=json"), "http://localhost:8000/api");
+        assert_eq!(api_url_from_ws("wss://example.com/connection/websocket"), "https://example.com/api");
+    }
+}
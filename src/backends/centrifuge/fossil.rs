@@ -0,0 +1,120 @@
+//! Applying fossil deltas (https://fossil-scm.org/home/doc/tip/www/delta_format.wiki),
+//! used by Centrifugo for `delta=fossil` subscriptions.
+
+use anyhow::bail;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn get_int(&mut self) -> u32 {
+        let mut value: u32 = 0;
+        while let Some(digit) = self.data.get(self.pos).and_then(|b| digit(*b)) {
+            value = (value << 6).wrapping_add(digit);
+            self.pos += 1;
+        }
+        value
+    }
+
+    fn get_char(&mut self) -> Option<u8> {
+        let c = self.data.get(self.pos).copied();
+        self.pos += 1;
+        c
+    }
+}
+
+// integers are written in base64 with "0-9A-Z_a-z~" digits
+fn digit(b: u8) -> Option<u32> {
+    match b {
+        b'0'..=b'9' => Some((b - b'0') as u32),
+        b'A'..=b'Z' => Some((b - b'A') as u32 + 10),
+        b'_' => Some(36),
+        b'a'..=b'z' => Some((b - b'a') as u32 + 37),
+        b'~' => Some(63),
+        _ => None,
+    }
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    let mut sum = [0u32; 4];
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        for (sum, b) in sum.iter_mut().zip(chunk) {
+            *sum = sum.wrapping_add(*b as u32);
+        }
+    }
+    let mut result = sum[3]
+        .wrapping_add(sum[2] << 8)
+        .wrapping_add(sum[1] << 16)
+        .wrapping_add(sum[0] << 24);
+    for (b, shift) in chunks.remainder().iter().zip([24, 16, 8]) {
+        result = result.wrapping_add((*b as u32) << shift);
+    }
+    result
+}
+
+pub fn apply(source: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut reader = Reader { data: delta, pos: 0 };
+    let limit = reader.get_int() as usize;
+    if reader.get_char() != Some(b'\n') {
+        bail!("size integer not terminated by '\\n'");
+    }
+
+    // size comes from the server, the checks below enforce it without trusting it for allocation
+    let mut output = Vec::with_capacity(limit.min(source.len() + delta.len()));
+    while reader.pos < delta.len() {
+        let count = reader.get_int() as usize;
+        match reader.get_char() {
+            Some(b'@') => {
+                let offset = reader.get_int() as usize;
+                if reader.pos < delta.len() && reader.get_char() != Some(b',') {
+                    bail!("copy command not terminated by ','");
+                }
+                if output.len() + count > limit {
+                    bail!("copy exceeds output file size");
+                }
+                let Some(data) = source.get(offset..offset + count) else {
+                    bail!("copy extends past end of input");
+                };
+                output.extend_from_slice(data);
+            }
+            Some(b':') => {
+                if output.len() + count > limit {
+                    bail!("insert command gives an output larger than predicted");
+                }
+                let Some(data) = delta.get(reader.pos..reader.pos + count) else {
+                    bail!("insert count exceeds size of delta");
+                };
+                output.extend_from_slice(data);
+                reader.pos += count;
+            }
+            Some(b';') => {
+                if count as u32 != checksum(&output) {
+                    bail!("bad checksum");
+                }
+                if output.len() != limit {
+                    bail!("generated size does not match predicted size");
+                }
+                return Ok(output);
+            }
+            _ => bail!("unknown delta operator"),
+        }
+    }
+    bail!("unterminated delta");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_delta() {
+        let delta = b"H\n6@0,6:there 5@6,OPwXW;";
+        assert_eq!(apply(b"hello world", delta).unwrap(), b"hello there world");
+        assert!(apply(b"hello_world", delta).is_err());
+        // predicted size of almost 4 GiB
+        assert!(apply(b"hello world", b"~~~~~~\n3:abc;").is_err());
+    }
+}
@@ -0,0 +1,560 @@
+//! Centrifugo client protocol (v2) over websocket, using message definitions from `tokio-centrifuge`.
+//!
+//! `tokio_centrifuge::client::Client` doesn't give access to subscription tokens, token refresh,
+//! history, presence and other parts of the protocol, so this is a minimal replacement for it:
+//! one connection with request/reply matching, pings, token refresh and automatic reconnects.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use futures_util::{SinkExt, StreamExt};
+use prost::Message as _;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::Instant;
+use tokio_centrifuge::config::{BackoffReconnect, Protocol, ReconnectStrategy};
+use tokio_centrifuge::errors::DisconnectErrorCode;
+use tokio_centrifuge::protocol::{
+    Command, ConnectRequest, ConnectResult, PushData, RawCommand, RawReply, RefreshRequest, Reply,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::mqtrait::ConnectionEvent;
+
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns a token for connect, or for refresh if the argument is set, `None` if there's none.
+pub type TokenFn = Arc<dyn Fn(bool) -> anyhow::Result<Option<String>> + Send + Sync>;
+
+/// Events delivered to listeners of a channel.
+#[derive(Debug)]
+pub enum Event {
+    Push(PushData),
+    Connected,
+    Disconnected { code: u32, reason: String, reconnect: bool },
+}
+
+#[derive(Default)]
+struct Shared {
+    info: Mutex<ConnectResult>,
+    routes: Mutex<HashMap<String, mpsc::UnboundedSender<Event>>>,
+}
+
+impl Shared {
+    fn broadcast(&self, event: impl Fn() -> Event) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.retain(|_, route| route.send(event()).is_ok());
+    }
+
+    fn route(&self, channel: &str, push: PushData) {
+        let mut routes = self.routes.lock().unwrap();
+        if let Some(route) = routes.get(channel) {
+            if route.send(Event::Push(push)).is_err() {
+                routes.remove(channel);
+            }
+        } else {
+            log::debug!("push for unknown channel: {}", channel);
+        }
+    }
+}
+
+pub struct Session {
+    commands: mpsc::UnboundedSender<(Command, oneshot::Sender<anyhow::Result<Reply>>)>,
+    shared: Arc<Shared>,
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+impl Session {
+    /// Connect to the server, the first connection attempt must succeed, after that
+    /// connection is re-established in the background until session is dropped.
+    pub async fn connect(url: &str, protocol: Protocol, name: &str, token: TokenFn) -> anyhow::Result<Self> {
+        let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+        let (connected_tx, connected_rx) = oneshot::channel();
+        let shared = Arc::new(Shared::default());
+        let (events, _) = broadcast::channel(16);
+
+        let connection = Connection {
+            url: url.to_owned(),
+            protocol,
+            name: name.to_owned(),
+            token,
+            shared: shared.clone(),
+            commands: commands_rx,
+            events: events.clone(),
+        };
+        tokio::spawn(connection.run(connected_tx));
+
+        connected_rx.await.map_err(|_| anyhow!("failed to connect"))??;
+        Ok(Self { commands: commands_tx, shared, events })
+    }
+
+    pub fn info(&self) -> ConnectResult {
+        self.shared.info.lock().unwrap().clone()
+    }
+
+    /// Receive pushes for a channel along with connection state changes,
+    /// should be called before subscribing to not miss anything.
+    pub fn listen(&self, channel: &str) -> mpsc::UnboundedReceiver<Event> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.shared.routes.lock().unwrap().insert(channel.to_owned(), tx);
+        rx
+    }
+
+    /// Connection state changes after the first connect.
+    pub fn connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Send a command and wait for reply, error replies are turned into errors.
+    pub async fn call(&self, command: Command) -> anyhow::Result<Reply> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.commands.send((command, reply_tx)).map_err(|_| anyhow!("connection closed"))?;
+        match tokio::time::timeout(REQUEST_TIMEOUT, reply_rx).await {
+            Ok(Ok(Ok(Reply::Error(err)))) => bail!("{} (code={})", err.message, err.code),
+            Ok(Ok(reply)) => reply,
+            Ok(Err(_)) => bail!("connection closed"),
+            Err(_) => bail!("request timed out"),
+        }
+    }
+}
+
+struct Connection {
+    url: String,
+    protocol: Protocol,
+    name: String,
+    token: TokenFn,
+    shared: Arc<Shared>,
+    commands: mpsc::UnboundedReceiver<(Command, oneshot::Sender<anyhow::Result<Reply>>)>,
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+// how connected session ended
+struct Disconnect {
+    code: u32,
+    reason: String,
+    reconnect: bool,
+}
+
+impl Connection {
+    async fn run(mut self, connected: oneshot::Sender<anyhow::Result<()>>) {
+        let backoff = BackoffReconnect::default();
+        let mut connected = Some(connected);
+        let mut attempt = 0;
+
+        loop {
+            if attempt > 0 {
+                let delay = backoff.time_before_next_attempt(attempt);
+                log::debug!("reconnecting attempt {}, delay={:?}", attempt, delay);
+                tokio::time::sleep(delay).await;
+            }
+
+            let result = self.run_once(&mut || {
+                attempt = 0;
+                if let Some(connected) = connected.take() {
+                    let _ = connected.send(Ok(()));
+                }
+            }).await;
+
+            let disconnect = match result {
+                Ok(disconnect) => disconnect,
+                Err(err) => {
+                    if let Some(connected) = connected.take() {
+                        let _ = connected.send(Err(err));
+                        return;
+                    }
+                    log::debug!("failed to connect: {}", err);
+                    let _ = self.events.send(ConnectionEvent::ReconnectFailed { error: err.to_string() });
+                    attempt += 1;
+                    continue;
+                }
+            };
+
+            log::debug!("disconnected (code={}, reason={})", disconnect.code, disconnect.reason);
+            let _ = self.events.send(ConnectionEvent::Disconnected {
+                // transport errors don't have a code
+                code: (disconnect.code != 0).then_some(disconnect.code),
+                reason: disconnect.reason.clone(),
+            });
+            self.shared.broadcast(|| Event::Disconnected {
+                code: disconnect.code,
+                reason: disconnect.reason.clone(),
+                reconnect: disconnect.reconnect,
+            });
+            if !disconnect.reconnect || self.commands.is_closed() {
+                // dropping routes lets listeners know that nothing else will arrive
+                self.shared.routes.lock().unwrap().clear();
+                return;
+            }
+            attempt += 1;
+        }
+    }
+
+    // returns error if connection wasn't established, or disconnect reason otherwise
+    async fn run_once(&mut self, on_connected: &mut impl FnMut()) -> anyhow::Result<Disconnect> {
+        log::debug!("connecting to {}", self.url);
+        let (stream, _) = tokio_tungstenite::connect_async(&self.url).await?;
+        let (mut write, mut read) = stream.split();
+
+        let token = (self.token)(false)?.unwrap_or_default();
+        let mut handshake = RawCommand::from(Command::Connect(ConnectRequest {
+            token,
+            name: self.name.clone(),
+            ..Default::default()
+        }));
+        handshake.id = 1;
+        write.send(encode(&handshake, self.protocol)?).await?;
+
+        let result = tokio::time::timeout(REQUEST_TIMEOUT, async {
+            while let Some(message) = read.next().await {
+                for reply in decode(&message?, self.protocol) {
+                    let reply = reply?;
+                    if reply.id != 1 {
+                        continue;
+                    }
+                    return match Reply::from(reply) {
+                        Reply::Connect(result) => Ok(result),
+                        Reply::Error(err) => Err(anyhow!("{} (code={})", err.message, err.code)),
+                        reply => Err(anyhow!("unexpected reply: {:?}", reply)),
+                    };
+                }
+            }
+            Err(anyhow!("connection closed during handshake"))
+        }).await.map_err(|_| anyhow!("handshake timed out"))??;
+
+        log::debug!("connected, client_id={}, version={}", result.client, result.version);
+        let pong = result.pong;
+        let refresh_at = result.expires.then(|| refresh_deadline(result.ttl));
+        *self.shared.info.lock().unwrap() = result;
+        // listeners added after the first connect shouldn't see it
+        self.shared.broadcast(|| Event::Connected);
+        let _ = self.events.send(ConnectionEvent::Connected);
+        on_connected();
+
+        // errors past this point are disconnects, not failed connection attempts
+        let disconnect = self.process(write, read, pong, refresh_at).await;
+        Ok(disconnect.unwrap_or_else(|err| Disconnect { code: 0, reason: err.to_string(), reconnect: true }))
+    }
+
+    async fn process(
+        &mut self,
+        mut write: impl SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin,
+        mut read: impl StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        pong: bool,
+        mut refresh_at: Option<Instant>,
+    ) -> anyhow::Result<Disconnect> {
+        let mut pending: HashMap<u32, oneshot::Sender<anyhow::Result<Reply>>> = HashMap::new();
+        let mut refresh_id = None;
+        let mut next_id = 2;
+
+        loop {
+            tokio::select! {
+                message = read.next() => {
+                    let message = match message {
+                        Some(Ok(Message::Close(frame))) => {
+                            let (code, reason) = frame
+                                .map(|frame| (u16::from(frame.code), frame.reason.to_string()))
+                                .unwrap_or((0, String::new()));
+                            let reconnect = DisconnectErrorCode(code).should_reconnect();
+                            return Ok(Disconnect { code: code as u32, reason, reconnect });
+                        }
+                        Some(Ok(message)) => message,
+                        Some(Err(err)) => {
+                            return Ok(Disconnect { code: 0, reason: err.to_string(), reconnect: true });
+                        }
+                        None => {
+                            return Ok(Disconnect { code: 0, reason: "connection closed".to_string(), reconnect: true });
+                        }
+                    };
+
+                    for reply in decode(&message, self.protocol) {
+                        let reply = match reply {
+                            Ok(reply) => reply,
+                            Err(err) => {
+                                log::warn!("failed to decode reply: {}", err);
+                                continue;
+                            }
+                        };
+                        let id = reply.id;
+                        match Reply::from(reply) {
+                            Reply::Empty if id == 0 => {
+                                // server ping
+                                if pong {
+                                    write.send(encode(&RawCommand::default(), self.protocol)?).await?;
+                                }
+                            }
+                            Reply::Push(push) => {
+                                if let PushData::Disconnect(disconnect) = push.data {
+                                    return Ok(Disconnect {
+                                        code: disconnect.code,
+                                        reason: disconnect.reason,
+                                        reconnect: disconnect.reconnect,
+                                    });
+                                }
+                                self.shared.route(&push.channel, push.data);
+                            }
+                            reply if Some(id) == refresh_id => {
+                                refresh_id = None;
+                                match reply {
+                                    Reply::Refresh(result) => {
+                                        log::debug!("connection token refreshed, expires={}, ttl={}", result.expires, result.ttl);
+                                        refresh_at = result.expires.then(|| refresh_deadline(result.ttl));
+                                    }
+                                    reply => log::warn!("failed to refresh connection token: {:?}", reply),
+                                }
+                            }
+                            reply => {
+                                if let Some(reply_tx) = pending.remove(&id) {
+                                    let _ = reply_tx.send(Ok(reply));
+                                } else {
+                                    log::debug!("unknown reply id={}", id);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                command = self.commands.recv() => {
+                    let Some((command, reply_tx)) = command else {
+                        let _ = write.close().await;
+                        return Ok(Disconnect { code: 0, reason: "session closed".to_string(), reconnect: false });
+                    };
+                    // callers that timed out don't need their replies anymore
+                    pending.retain(|_, reply_tx| !reply_tx.is_closed());
+                    let mut command = RawCommand::from(command);
+                    command.id = next_id;
+                    next_id = next_id.wrapping_add(1).max(2);
+                    match encode(&command, self.protocol) {
+                        Ok(message) => {
+                            pending.insert(command.id, reply_tx);
+                            write.send(message).await?;
+                        }
+                        Err(err) => {
+                            let _ = reply_tx.send(Err(err));
+                        }
+                    }
+                }
+
+                _ = sleep_until(refresh_at) => {
+                    refresh_at = None;
+                    match (self.token)(true) {
+                        Ok(Some(token)) => {
+                            let mut command = RawCommand::from(Command::Refresh(RefreshRequest { token }));
+                            command.id = next_id;
+                            refresh_id = Some(next_id);
+                            next_id = next_id.wrapping_add(1).max(2);
+                            write.send(encode(&command, self.protocol)?).await?;
+                        }
+                        Ok(None) => log::warn!("connection token expires soon and can't be refreshed"),
+                        Err(err) => log::error!("failed to get new connection token: {}", err),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Refresh tokens a bit earlier than they expire, to account for network delays.
+pub fn refresh_deadline(ttl: u32) -> Instant {
+    let ttl = Duration::from_secs(ttl as u64);
+    Instant::now() + ttl.saturating_sub(ttl / 10).saturating_sub(Duration::from_secs(1))
+}
+
+pub async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn encode(command: &RawCommand, protocol: Protocol) -> anyhow::Result<Message> {
+    Ok(match protocol {
+        Protocol::Json => Message::Text(serde_json::to_string(command)?.into()),
+        Protocol::Protobuf => Message::Binary(command.encode_length_delimited_to_vec().into()),
+    })
+}
+
+fn decode(message: &Message, protocol: Protocol) -> Vec<anyhow::Result<RawReply>> {
+    let data: &[u8] = match message {
+        Message::Text(text) => text.as_bytes(),
+        Message::Binary(data) => data,
+        _ => return vec![],
+    };
+
+    match protocol {
+        // newline-delimited json objects
+        Protocol::Json => data
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .map(|line| serde_json::from_slice(line).map_err(Into::into))
+            .collect(),
+        // varint length-delimited protobuf messages
+        Protocol::Protobuf => {
+            let mut data = data;
+            let mut replies = vec![];
+            while !data.is_empty() {
+                match RawReply::decode_length_delimited(&mut data) {
+                    Ok(reply) => replies.push(Ok(reply)),
+                    Err(err) => {
+                        replies.push(Err(err.into()));
+                        break;
+                    }
+                }
+            }
+            replies
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_centrifuge::protocol::{PublishRequest, RpcRequest, SubscribeRequest};
+    use tokio_tungstenite::WebSocketStream;
+
+    use super::*;
+
+    async fn listen() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    async fn accept(listener: &TcpListener) -> WebSocketStream<TcpStream> {
+        let (stream, _) = listener.accept().await.unwrap();
+        tokio_tungstenite::accept_async(stream).await.unwrap()
+    }
+
+    async fn recv(socket: &mut WebSocketStream<TcpStream>) -> RawCommand {
+        let message = socket.next().await.unwrap().unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    async fn send(socket: &mut WebSocketStream<TcpStream>, reply: serde_json::Value) {
+        socket.send(Message::Text(reply.to_string().into())).await.unwrap();
+    }
+
+    /// Accepts the connect command and replies with given result.
+    async fn handshake(listener: &TcpListener, result: serde_json::Value) -> (WebSocketStream<TcpStream>, RawCommand) {
+        let mut socket = accept(listener).await;
+        let connect = recv(&mut socket).await;
+        send(&mut socket, json!({ "id": connect.id, "connect": result })).await;
+        (socket, connect)
+    }
+
+    fn token() -> TokenFn {
+        Arc::new(|refresh| Ok(Some(if refresh { "refreshed" } else { "initial" }.to_string())))
+    }
+
+    async fn next<T>(receiver: &mut mpsc::UnboundedReceiver<T>) -> T {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn subscribe_and_publish() {
+        let (listener, url) = listen().await;
+        let server = tokio::spawn(async move {
+            let (mut socket, connect) = handshake(&listener, json!({ "client": "c1", "version": "6.0.0" })).await;
+            let subscribe = recv(&mut socket).await;
+            send(&mut socket, json!({ "id": subscribe.id, "subscribe": {} })).await;
+            send(&mut socket, json!({ "push": { "channel": "news", "pub": { "data": { "text": "hi" } } } })).await;
+            let publish = recv(&mut socket).await;
+            send(&mut socket, json!({ "id": publish.id, "publish": {} })).await;
+            (connect, subscribe, publish)
+        });
+
+        let session = Session::connect(&url, Protocol::Json, "mqcat-test", token()).await.unwrap();
+        assert_eq!(session.info().client, "c1");
+        let mut events = session.listen("news");
+        let reply = session.call(Command::Subscribe(SubscribeRequest {
+            channel: "news".to_string(),
+            ..Default::default()
+        })).await.unwrap();
+        assert!(matches!(reply, Reply::Subscribe(_)));
+        match next(&mut events).await {
+            Event::Push(PushData::Publication(publication)) => assert_eq!(publication.data, br#"{"text":"hi"}"#),
+            event => panic!("unexpected event: {:?}", event),
+        }
+        let reply = session.call(Command::Publish(PublishRequest {
+            channel: "news".to_string(),
+            data: br#"{"n":1}"#.to_vec(),
+        })).await.unwrap();
+        assert!(matches!(reply, Reply::Publish(_)));
+
+        let (connect, subscribe, publish) = server.await.unwrap();
+        let connect = connect.connect.unwrap();
+        assert_eq!((connect.token.as_str(), connect.name.as_str()), ("initial", "mqcat-test"));
+        assert_eq!(subscribe.subscribe.unwrap().channel, "news");
+        assert_eq!(publish.publish.unwrap().data, br#"{"n":1}"#);
+    }
+
+    #[tokio::test]
+    async fn reply_correlation() {
+        let (listener, url) = listen().await;
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = handshake(&listener, json!({ "client": "c1", "pong": true })).await;
+            // server ping, answered with an empty command
+            send(&mut socket, json!({})).await;
+            let mut rpcs = HashMap::new();
+            let mut pongs = 0;
+            for _ in 0..3 {
+                let command = recv(&mut socket).await;
+                match command.rpc {
+                    Some(rpc) => {
+                        rpcs.insert(rpc.method, command.id);
+                    }
+                    None => pongs += 1,
+                }
+            }
+            // replies in reverse order, with an unknown id in between
+            send(&mut socket, json!({ "id": rpcs["b"], "rpc": { "data": { "method": "b" } } })).await;
+            send(&mut socket, json!({ "id": 999, "rpc": {} })).await;
+            send(&mut socket, json!({ "id": rpcs["a"], "error": { "code": 103, "message": "permission denied" } })).await;
+            pongs
+        });
+
+        let session = Session::connect(&url, Protocol::Json, "mqcat-test", token()).await.unwrap();
+        let rpc = |method: &str| session.call(Command::Rpc(RpcRequest {
+            method: method.to_string(),
+            ..Default::default()
+        }));
+        let (a, b) = tokio::join!(rpc("a"), rpc("b"));
+        assert_eq!(a.unwrap_err().to_string(), "permission denied (code=103)");
+        match b.unwrap() {
+            Reply::Rpc(result) => assert_eq!(result.data, br#"{"method":"b"}"#),
+            reply => panic!("unexpected reply: {:?}", reply),
+        }
+        assert_eq!(server.await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn refresh_and_reconnect() {
+        let (listener, url) = listen().await;
+        let server = tokio::spawn(async move {
+            // token expires within a second
+            let (mut socket, _) = handshake(&listener, json!({ "client": "c1", "expires": true, "ttl": 2 })).await;
+            let refresh = recv(&mut socket).await;
+            send(&mut socket, json!({ "id": refresh.id, "refresh": { "expires": false } })).await;
+            send(&mut socket, json!({ "push": { "disconnect": { "code": 3001, "reason": "shutdown", "reconnect": true } } })).await;
+            let (_socket, connect) = handshake(&listener, json!({ "client": "c2" })).await;
+            (refresh, connect)
+        });
+
+        let session = Session::connect(&url, Protocol::Json, "mqcat-test", token()).await.unwrap();
+        let mut events = session.listen("news");
+        let mut connection_events = session.connection_events();
+        match next(&mut events).await {
+            Event::Disconnected { code, reason, reconnect } => assert_eq!((code, reason.as_str(), reconnect), (3001, "shutdown", true)),
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert!(matches!(next(&mut events).await, Event::Connected));
+        assert_eq!(session.info().client, "c2");
+        assert!(matches!(connection_events.recv().await.unwrap(), ConnectionEvent::Disconnected { code: Some(3001), .. }));
+        assert!(matches!(connection_events.recv().await.unwrap(), ConnectionEvent::Connected));
+
+        let (refresh, connect) = server.await.unwrap();
+        assert_eq!(refresh.refresh.unwrap().token, "refreshed");
+        assert_eq!(connect.connect.unwrap().token, "initial");
+    }
+}
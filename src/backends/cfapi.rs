@@ -0,0 +1,360 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use clap::{Args, Subcommand};
+use futures_util::Stream;
+use serde_json::{Value, json};
+
+use super::centrifuge::api::{self, ApiClient};
+use crate::cli::{NoOptions, data_or_stdin, parse_header};
+use crate::mqtrait::{Frame, MessageQueue, Publisher};
+use crate::utils::format_info;
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Centrifugo API Options")]
+pub struct CfApiOptions {
+    #[arg(global = true, long, help = "server API key")]
+    api_key: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Centrifugo API Publish Options")]
+pub struct CfApiPublishOptions {
+    #[arg(long, help = "idempotency key, repeated publications with the same key are dropped by server (pub --count adds -<n> to keys of messages after the first)")]
+    idempotency_key: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CfApiCommands {
+    #[command(about = "publish the same message to many channels at once")]
+    Broadcast {
+        #[arg(short, long = "channel", help = "channel name", required = true)]
+        channels: Vec<String>,
+        #[arg(help = "data to publish (read from stdin if not provided)")]
+        data: Option<String>,
+        #[arg(short = 'H', long, help = "add tag to the message", value_parser = parse_header)]
+        header: Vec<(String, String)>,
+        #[command(flatten)]
+        options: CfApiPublishOptions,
+    },
+
+    #[command(about = "list active channels")]
+    Channels {
+        #[arg(help = "only show channels matching a pattern, e.g. \"news:*\"")]
+        pattern: Option<String>,
+        #[arg(long, help = "print channels as json")]
+        json: bool,
+    },
+
+    #[command(about = "manage connections of a user", subcommand)]
+    User(UserCommands),
+
+    #[command(about = "remove channel history")]
+    HistoryRemove {
+        #[arg(help = "channel name")]
+        channel: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UserCommands {
+    #[command(about = "disconnect user")]
+    Disconnect {
+        #[arg(help = "user id")]
+        user: String,
+        #[command(flatten)]
+        target: UserTarget,
+        #[arg(long, help = "disconnect code (3000 if only reason is set)")]
+        code: Option<u32>,
+        #[arg(long, help = "disconnect reason")]
+        reason: Option<String>,
+    },
+
+    #[command(about = "subscribe user to a channel")]
+    Subscribe {
+        #[arg(help = "user id")]
+        user: String,
+        #[arg(help = "channel name")]
+        channel: String,
+        #[command(flatten)]
+        target: UserTarget,
+    },
+
+    #[command(about = "unsubscribe user from a channel")]
+    Unsubscribe {
+        #[arg(help = "user id")]
+        user: String,
+        #[arg(help = "channel name")]
+        channel: String,
+        #[command(flatten)]
+        target: UserTarget,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct UserTarget {
+    #[arg(long, help = "only apply to this client id")]
+    client: Option<String>,
+    #[arg(long, help = "only apply to this session id")]
+    session: Option<String>,
+}
+
+impl UserTarget {
+    fn params(self, mut params: Value) -> Value {
+        if let Some(client) = self.client {
+            params["client"] = client.into();
+        }
+        if let Some(session) = self.session {
+            params["session"] = session.into();
+        }
+        params
+    }
+}
+
+fn field(value: &Value, key: &str) -> String {
+    match value.get(key) {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Null) | None => "-".to_string(),
+        Some(value) => value.to_string(),
+    }
+}
+
+fn tags(headers: &[(String, String)]) -> Value {
+    headers.iter().map(|(k, v)| (k.clone(), Value::from(v.clone()))).collect::<serde_json::Map<_, _>>().into()
+}
+
+/// Every message of `pub --count` needs its own key, otherwise the server drops all but the first.
+fn message_key(key: &str, n: u32) -> String {
+    match n {
+        1 => key.to_owned(),
+        n => format!("{}-{}", key, n),
+    }
+}
+
+fn publish_params(headers: &[(String, String)], payload: &[u8], idempotency_key: Option<String>) -> Value {
+    let mut params = json!({});
+    api::data_params(&mut params, payload);
+    if headers.len() > 0 {
+        params["tags"] = tags(headers);
+    }
+    if let Some(key) = idempotency_key {
+        params["idempotency_key"] = key.into();
+    }
+    params
+}
+
+struct CfApiMQ {
+    api: ApiClient,
+}
+
+impl CfApiMQ {
+    async fn publish_with_key(&self, topic: &str, headers: &[(String, String)], payload: &[u8], idempotency_key: Option<String>) -> anyhow::Result<()> {
+        let mut params = publish_params(headers, payload, idempotency_key);
+        params["channel"] = topic.into();
+        let result = self.api.call("publish", params).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        if result.get("offset").is_some() {
+            log::debug!("published at offset {}, epoch {}", field(&result, "offset"), field(&result, "epoch"));
+        }
+        Ok(())
+    }
+}
+
+pub struct CfApiPublisher<'a> {
+    mq: &'a CfApiMQ,
+    topic: String,
+    idempotency_key: Option<String>,
+    sent: AtomicU32,
+}
+
+impl Publisher for CfApiPublisher<'_> {
+    async fn publish(&self, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        let n = self.sent.fetch_add(1, Ordering::Relaxed) + 1;
+        let key = self.idempotency_key.as_deref().map(|key| message_key(key, n));
+        self.mq.publish_with_key(&self.topic, headers, payload, key).await
+    }
+}
+
+impl MessageQueue for CfApiMQ {
+    type Options = CfApiOptions;
+    type Commands = CfApiCommands;
+    type PublishOptions = CfApiPublishOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = CfApiPublisher<'a>;
+
+    async fn connect(addr: Option<&str>, options: &CfApiOptions) -> anyhow::Result<Self> {
+        let url = addr.unwrap_or("http://localhost:8000/api");
+        Ok(Self {
+            api: ApiClient::new(url, options.api_key.as_deref()),
+        })
+    }
+
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
+        let result = self.api.call("info", json!({})).await
+            .map_err(|err| anyhow!("failed to get info: {}", err))?;
+
+        let mut info = vec![];
+        info.push(("URL", self.api.url().to_owned()));
+
+        for node in result["nodes"].as_array().into_iter().flatten() {
+            info.push(("", String::new()));
+            info.push(("Node", format!("{} ({})", field(node, "name"), field(node, "uid"))));
+            info.push(("Version", field(node, "version")));
+            if let Some(uptime) = node["uptime"].as_u64() {
+                info.push(("Uptime", format!("{:?}", Duration::from_secs(uptime))));
+            }
+            info.push(("Clients", field(node, "num_clients")));
+            info.push(("Users", field(node, "num_users")));
+            info.push(("Subscriptions", field(node, "num_subs")));
+            info.push(("Channels", field(node, "num_channels")));
+        }
+
+        Ok(format_info(&info, json))
+    }
+
+    async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        self.publish_with_key(topic, headers, payload, None).await
+    }
+
+    async fn publisher(&self, topic: &str, options: &CfApiPublishOptions) -> anyhow::Result<CfApiPublisher<'_>> {
+        Ok(CfApiPublisher { mq: self, topic: topic.to_owned(), idempotency_key: options.idempotency_key.clone(), sent: AtomicU32::new(0) })
+    }
+
+    fn subscribe(&self, _topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        futures_util::stream::once(async {
+            Err(anyhow!("subscribing is not possible with server API, use cfj or cfp transport"))
+        })
+    }
+
+    async fn request(&self, _topic: &str, _headers: &[(String, String)], _payload: &[u8]) -> anyhow::Result<Frame> {
+        bail!("rpc is not possible with server API, use cfj or cfp transport");
+    }
+
+    async fn command(addr: Option<&str>, options: &CfApiOptions, command: CfApiCommands) -> anyhow::Result<()> {
+        let mq = Self::connect(addr, options).await?;
+        match command {
+            CfApiCommands::Broadcast { channels, data, header, options } => {
+                let data = data_or_stdin(data)?;
+                let mut params = publish_params(&header, &data, options.idempotency_key);
+                params["channels"] = channels.clone().into();
+                let result = mq.api.call("broadcast", params).await
+                    .map_err(|err| anyhow!("failed to broadcast: {}", err))?;
+
+                // broadcast succeeds even if some of the channels failed
+                let responses = result["responses"].as_array().cloned().unwrap_or_default();
+                let mut failed = 0;
+                for (channel, response) in channels.iter().zip(responses) {
+                    if let Some(error) = response.get("error") {
+                        log::error!("failed to publish to \"{}\": {} (code={})", channel, field(error, "message"), field(error, "code"));
+                        failed += 1;
+                    }
+                }
+                log::info!("broadcast {} bytes to {} channels", data.len(), channels.len() - failed);
+            }
+            CfApiCommands::Channels { pattern, json } => {
+                let mut params = json!({});
+                if let Some(pattern) = pattern {
+                    params["pattern"] = pattern.into();
+                }
+                let result = mq.api.call("channels", params).await
+                    .map_err(|err| anyhow!("failed to list channels: {}", err))?;
+
+                let mut channels: Vec<(String, String)> = result["channels"].as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(|(name, info)| (name.clone(), field(info, "num_clients")))
+                    .collect();
+                channels.sort();
+
+                let info: Vec<(&str, String)> = channels.iter()
+                    .map(|(name, clients)| (name.as_str(), clients.clone()))
+                    .collect();
+                std::io::stdout().write_all(format_info(&info, json).as_bytes())?;
+                std::io::stdout().flush()?;
+            }
+            CfApiCommands::User(UserCommands::Disconnect { user, target, code, reason }) => {
+                let mut params = target.params(json!({ "user": user }));
+                if code.is_some() || reason.is_some() {
+                    params["disconnect"] = json!({
+                        "code": code.unwrap_or(3000),
+                        "reason": reason.unwrap_or_default(),
+                    });
+                }
+                mq.api.call("disconnect", params).await
+                    .map_err(|err| anyhow!("failed to disconnect: {}", err))?;
+                log::info!("disconnected user \"{}\"", user);
+            }
+            CfApiCommands::User(UserCommands::Subscribe { user, channel, target }) => {
+                let params = target.params(json!({ "user": user, "channel": channel }));
+                mq.api.call("subscribe", params).await
+                    .map_err(|err| anyhow!("failed to subscribe: {}", err))?;
+                log::info!("subscribed user \"{}\" to \"{}\"", user, channel);
+            }
+            CfApiCommands::User(UserCommands::Unsubscribe { user, channel, target }) => {
+                let params = target.params(json!({ "user": user, "channel": channel }));
+                mq.api.call("unsubscribe", params).await
+                    .map_err(|err| anyhow!("failed to unsubscribe: {}", err))?;
+                log::info!("unsubscribed user \"{}\" from \"{}\"", user, channel);
+            }
+            CfApiCommands::HistoryRemove { channel } => {
+                mq.api.call("history_remove", json!({ "channel": channel })).await
+                    .map_err(|err| anyhow!("failed to remove history: {}", err))?;
+                log::info!("removed history of \"{}\"", channel);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn run(args: impl Iterator<Item = String>) {
+    crate::cli::run::<CfApiMQ>(args).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::http_stand_in;
+
+    fn json_response(body: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    #[tokio::test]
+    async fn publish_with_tags() {
+        let (addr, handle) = http_stand_in(json_response(r#"{"result":{"offset":1,"epoch":"xyz"}}"#));
+        let url = format!("http://{}/api", addr);
+        let mq = CfApiMQ::connect(Some(&url), &CfApiOptions { api_key: Some("secret".to_string()) }).await.unwrap();
+        let publisher = mq.publisher("news", &CfApiPublishOptions { idempotency_key: Some("once".to_string()) }).await.unwrap();
+        publisher.publish(&[("lang".to_string(), "en".to_string())], br#"{"text":"hello"}"#).await.unwrap();
+
+        let request = handle.join().unwrap();
+        assert_eq!(request.path, "/api/publish");
+        assert!(request.headers.contains("x-api-key: secret"));
+        assert_eq!(serde_json::from_slice::<Value>(&request.body).unwrap(), json!({
+            "channel": "news",
+            "data": { "text": "hello" },
+            "tags": { "lang": "en" },
+            "idempotency_key": "once",
+        }));
+    }
+
+    #[tokio::test]
+    async fn error_reply() {
+        let (addr, handle) = http_stand_in(json_response(r#"{"error":{"code":102,"message":"unknown channel"}}"#));
+        let url = format!("http://{}/api", addr);
+        let mq = CfApiMQ::connect(Some(&url), &CfApiOptions { api_key: None }).await.unwrap();
+        let err = mq.publish("nope", &[], b"binary\xff").await.unwrap_err();
+        assert_eq!(err.to_string(), "failed to publish: unknown channel (code=102)");
+
+        let body: Value = serde_json::from_slice(&handle.join().unwrap().body).unwrap();
+        assert_eq!(body["b64data"], "YmluYXJ5/w==");
+    }
+
+    #[test]
+    fn idempotency_key_per_message() {
+        assert_eq!(message_key("batch", 1), "batch");
+        assert_eq!(message_key("batch", 2), "batch-2");
+    }
+}
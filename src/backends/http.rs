@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use clap::Args;
+use futures_util::Stream;
+use ureq::http::{Method, Request, Response};
+use ureq::{Agent, Body};
+
+use crate::cli::{NoCommands, NoOptions, parse_duration};
+use crate::mqtrait::{Frame, MessageQueue, TopicPublisher};
+use crate::utils::{format_info, topic_url, url_path};
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "HTTP Options")]
+pub struct HttpOptions {
+    #[arg(global = true, long, help = "request method for pub/req", default_value = "POST")]
+    method: Method,
+    #[arg(global = true, long, help = "request timeout", default_value = "10s", value_parser = parse_duration)]
+    timeout: Duration,
+}
+
+/// Status line and response headers go to frame headers, body is the payload.
+pub(crate) fn response_to_frame(topic: &str, response: &Response<Body>, body: Vec<u8>) -> Frame {
+    let mut frame = Frame {
+        topic: topic.to_owned(),
+        headers: Default::default(),
+        payload: body,
+    };
+    let status = response.status();
+    frame.headers.insert("Status".to_string(), vec![format!("{} {}", status.as_u16(), status.canonical_reason().unwrap_or_default())]);
+    for (key, value) in response.headers() {
+        frame.headers.entry(key.to_string()).or_default().push(String::from_utf8_lossy(value.as_bytes()).into_owned());
+    }
+    frame
+}
+
+struct HttpMQ {
+    url: String,
+    agent: Agent,
+    options: HttpOptions,
+}
+
+impl HttpMQ {
+    async fn send(&self, method: Method, url: String, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<Frame> {
+        let mut request = Request::builder().method(method).uri(&url);
+        for (key, value) in headers {
+            request = request.header(key, value);
+        }
+        let request = request.body(payload.to_vec())?;
+        let agent = self.agent.clone();
+
+        // ureq is blocking, same as the centrifuge server API client
+        tokio::task::spawn_blocking(move || -> anyhow::Result<Frame> {
+            let mut response = agent.run(request)?;
+            let body = response.body_mut().read_to_vec()?;
+            Ok(response_to_frame(url_path(&url), &response, body))
+        }).await?
+    }
+}
+
+impl MessageQueue for HttpMQ {
+    type Options = HttpOptions;
+    type Commands = NoCommands;
+    type PublishOptions = NoOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = TopicPublisher<'a, Self>;
+
+    async fn connect(addr: Option<&str>, options: &HttpOptions) -> anyhow::Result<Self> {
+        let url = addr.unwrap_or("http://localhost:8080").to_owned();
+        let agent = Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(options.timeout))
+            .build()
+            .into();
+        Ok(Self { url, agent, options: options.clone() })
+    }
+
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
+        let response = self.send(Method::HEAD, self.url.clone(), &[], &[]).await
+            .map_err(|err| anyhow!("request failed: {}", err))?;
+
+        let mut info = vec![("URL", self.url.clone())];
+        for (key, values) in &response.headers {
+            for value in values {
+                info.push((key.as_str(), value.clone()));
+            }
+        }
+        Ok(format_info(&info, json))
+    }
+
+    async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        let response = self.send(self.options.method.clone(), topic_url(&self.url, topic), headers, payload).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        let status = &response.headers["Status"][0];
+        if !status.starts_with('2') {
+            bail!("failed to publish: http status {}: {}", status, String::from_utf8_lossy(&response.payload).trim());
+        }
+        log::debug!("http status {}", status);
+        Ok(())
+    }
+
+    async fn publisher(&self, topic: &str, _options: &NoOptions) -> anyhow::Result<TopicPublisher<'_, Self>> {
+        Ok(TopicPublisher::new(self, topic))
+    }
+
+    fn subscribe(&self, _topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        futures_util::stream::once(async { Err(anyhow!("subscribe is not supported by http, use sse+http:// for event streams")) })
+    }
+
+    async fn request(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<Frame> {
+        let response = self.send(self.options.method.clone(), topic_url(&self.url, topic), headers, payload).await
+            .map_err(|err| anyhow!("request failed: {}", err))?;
+        let status = &response.headers["Status"][0];
+        if !status.starts_with('2') {
+            log::warn!("http status {}", status);
+        }
+        Ok(response)
+    }
+
+    async fn command(_addr: Option<&str>, _options: &HttpOptions, command: NoCommands) -> anyhow::Result<()> {
+        match command {}
+    }
+}
+
+pub async fn run(args: impl Iterator<Item = String>) {
+    crate::cli::run::<HttpMQ>(args).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::http_stand_in;
+
+    #[tokio::test]
+    async fn post_request() {
+        let (addr, handle) = http_stand_in("HTTP/1.1 201 Created\r\nX-Request-Id: 7\r\nContent-Length: 2\r\n\r\nok".to_string());
+        let url = format!("http://{}/hooks", addr);
+        let options = HttpOptions { method: Method::POST, timeout: Duration::from_secs(10) };
+        let mq = HttpMQ::connect(Some(&url), &options).await.unwrap();
+        let headers = [("X-Signature".to_string(), "abc".to_string())];
+        let reply = mq.request("deploy", &headers, b"payload").await.unwrap();
+        assert_eq!(reply.topic, "/hooks/deploy");
+        assert_eq!(reply.headers["Status"], ["201 Created"]);
+        assert_eq!(reply.headers["x-request-id"], ["7"]);
+        assert_eq!(reply.payload, b"ok");
+
+        let request = handle.join().unwrap();
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/hooks/deploy"));
+        assert!(request.headers.contains("x-signature: abc"));
+        assert_eq!(request.body, b"payload");
+    }
+}
@@ -0,0 +1,381 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail};
+use clap::{Args, ValueEnum};
+use futures_util::Stream;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::{Header, Headers, Message, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::{ClientConfig, Offset, Timestamp, TopicPartitionList};
+
+use crate::cli::{NoCommands, NoOptions, parse_duration};
+use crate::mqtrait::{Frame, MessageQueue, Publisher};
+use crate::utils::format_info;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Kafka Options")]
+pub struct KafkaOptions {
+    #[arg(global = true, long, help = "consumer group, offsets are committed only when set [default: mqcat-<pid>]")]
+    group: Option<String>,
+    #[arg(global = true, long, help = "where to start without committed offset: earliest, latest, or timestamp to seek to (ms since epoch, or duration ago like 10m)", default_value = "latest", value_parser = parse_offset_reset)]
+    offset_reset: OffsetReset,
+    #[arg(global = true, long, help = "consume only these partitions, or produce to this partition")]
+    partition: Vec<i32>,
+    #[arg(global = true, long, help = "set librdkafka property, e.g. --cfg security.protocol=SASL_SSL", value_parser = parse_cfg)]
+    cfg: Vec<(String, String)>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Kafka Publish Options")]
+pub struct KafkaPublishOptions {
+    #[arg(long, help = "record key")]
+    key: Option<String>,
+    #[arg(long, help = "partitioner for records without --partition", value_enum)]
+    partitioner: Option<Partitioner>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OffsetReset {
+    Earliest,
+    Latest,
+    /// milliseconds since epoch
+    Timestamp(i64),
+}
+
+fn parse_offset_reset(s: &str) -> Result<OffsetReset, String> {
+    match s {
+        "earliest" => return Ok(OffsetReset::Earliest),
+        "latest" => return Ok(OffsetReset::Latest),
+        _ => {}
+    }
+    if let Ok(timestamp) = s.parse() {
+        return Ok(OffsetReset::Timestamp(timestamp));
+    }
+    let ago = parse_duration(s)
+        .map_err(|_| "expected earliest, latest, timestamp in ms or duration".to_string())?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok(OffsetReset::Timestamp(now.saturating_sub(ago).as_millis() as i64))
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Partitioner {
+    Random,
+    Consistent,
+    ConsistentRandom,
+    Murmur2,
+    Murmur2Random,
+    Fnv1a,
+    Fnv1aRandom,
+}
+
+impl Partitioner {
+    fn as_str(self) -> &'static str {
+        match self {
+            Partitioner::Random => "random",
+            Partitioner::Consistent => "consistent",
+            Partitioner::ConsistentRandom => "consistent_random",
+            Partitioner::Murmur2 => "murmur2",
+            Partitioner::Murmur2Random => "murmur2_random",
+            Partitioner::Fnv1a => "fnv1a",
+            Partitioner::Fnv1aRandom => "fnv1a_random",
+        }
+    }
+}
+
+fn parse_cfg(s: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = s.split_once('=') else {
+        return Err("property must be in the format of \"key=value\"".to_string());
+    };
+    Ok((key.trim().to_string(), value.trim().to_string()))
+}
+
+fn message_to_frame(message: &impl Message) -> Frame {
+    let mut frame = Frame {
+        topic: message.topic().to_owned(),
+        headers: Default::default(),
+        payload: message.payload().unwrap_or_default().to_vec(),
+    };
+    frame.headers.insert("Partition".to_string(), vec![message.partition().to_string()]);
+    frame.headers.insert("Offset".to_string(), vec![message.offset().to_string()]);
+    match message.timestamp() {
+        Timestamp::NotAvailable => {}
+        Timestamp::CreateTime(ms) => { frame.headers.insert("Timestamp".to_string(), vec![format!("{} (create time)", ms)]); }
+        Timestamp::LogAppendTime(ms) => { frame.headers.insert("Timestamp".to_string(), vec![format!("{} (log append time)", ms)]); }
+    }
+    if let Some(key) = message.key() {
+        frame.headers.insert("Key".to_string(), vec![String::from_utf8_lossy(key).into_owned()]);
+    }
+    if let Some(headers) = message.headers() {
+        for header in headers.iter() {
+            let value = header.value.map(String::from_utf8_lossy).unwrap_or_default();
+            frame.headers.entry(header.key.to_owned()).or_default().push(value.into_owned());
+        }
+    }
+    frame
+}
+
+impl KafkaOptions {
+    fn config(&self, servers: &str) -> ClientConfig {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", servers);
+        for (key, value) in &self.cfg {
+            config.set(key, value);
+        }
+        config
+    }
+
+    // producer-only properties are kept out of the consumer config, librdkafka warns about them
+    fn producer(&self, servers: &str, partitioner: Option<Partitioner>) -> anyhow::Result<FutureProducer> {
+        let mut config = self.config(servers);
+        // --cfg takes precedence
+        if config.get("message.timeout.ms").is_none() {
+            config.set("message.timeout.ms", TIMEOUT.as_millis().to_string());
+        }
+        if let Some(partitioner) = partitioner && config.get("partitioner").is_none() {
+            config.set("partitioner", partitioner.as_str());
+        }
+        Ok(config.create()?)
+    }
+
+    /// Partition to produce to, --partition takes a list only for consuming.
+    fn produce_partition(&self) -> anyhow::Result<Option<i32>> {
+        match self.partition[..] {
+            [] => Ok(None),
+            [partition] => Ok(Some(partition)),
+            _ => bail!("only one --partition can be used for pub"),
+        }
+    }
+}
+
+async fn send(producer: &FutureProducer, topic: &str, key: Option<&str>, partition: Option<i32>, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+    let mut kafka_headers = OwnedHeaders::new_with_capacity(headers.len());
+    for (key, value) in headers {
+        kafka_headers = kafka_headers.insert(Header { key, value: Some(value) });
+    }
+
+    let mut record = FutureRecord::to(topic)
+        .payload(payload)
+        .headers(kafka_headers);
+    if let Some(key) = key {
+        record = record.key(key);
+    }
+    if let Some(partition) = partition {
+        record = record.partition(partition);
+    }
+
+    let (partition, offset) = producer.send(record, TIMEOUT).await
+        .map_err(|(err, _)| anyhow!("failed to publish: {}", err))?;
+    log::debug!("delivered to partition {} at offset {}", partition, offset);
+    Ok(())
+}
+
+pub struct KafkaPublisher {
+    producer: FutureProducer,
+    topic: String,
+    key: Option<String>,
+    partition: Option<i32>,
+}
+
+impl Publisher for KafkaPublisher {
+    async fn publish(&self, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        send(&self.producer, &self.topic, self.key.as_deref(), self.partition, headers, payload).await
+    }
+}
+
+struct KafkaMQ {
+    servers: String,
+    producer: FutureProducer,
+    options: KafkaOptions,
+}
+
+impl KafkaMQ {
+    fn consumer(&self) -> anyhow::Result<StreamConsumer> {
+        let mut config = self.options.config(&self.servers);
+        match &self.options.group {
+            Some(group) => config.set("group.id", group),
+            // reading without a group shouldn't affect anyone else
+            None => config
+                .set("group.id", format!("mqcat-{}", std::process::id()))
+                .set("enable.auto.commit", "false"),
+        };
+        config.set("auto.offset.reset", match self.options.offset_reset {
+            OffsetReset::Earliest => "earliest",
+            _ => "latest",
+        });
+        Ok(config.create()?)
+    }
+
+    /// Assign partitions explicitly, used for partition selection and seeking by timestamp
+    /// since both don't work with group rebalancing.
+    fn assign(consumer: &StreamConsumer, topic: &str, partitions: &[i32], offset_reset: OffsetReset) -> anyhow::Result<()> {
+        let partitions = if partitions.is_empty() {
+            let metadata = consumer.fetch_metadata(Some(topic), TIMEOUT)?;
+            let Some(metadata) = metadata.topics().first().filter(|t| t.error().is_none()) else {
+                bail!("topic \"{}\" not found", topic);
+            };
+            metadata.partitions().iter().map(|p| p.id()).collect()
+        } else {
+            partitions.to_vec()
+        };
+
+        let mut assignment = TopicPartitionList::new();
+        for partition in partitions {
+            let offset = match offset_reset {
+                OffsetReset::Timestamp(ms) => Offset::Offset(ms),
+                _ => Offset::Stored,
+            };
+            assignment.add_partition_offset(topic, partition, offset)?;
+        }
+        if let OffsetReset::Timestamp(_) = offset_reset {
+            // partitions without records after timestamp resolve to end
+            assignment = consumer.offsets_for_times(assignment, TIMEOUT)?;
+        }
+        for element in assignment.elements() {
+            log::debug!("assigned partition {} at {:?}", element.partition(), element.offset());
+        }
+        consumer.assign(&assignment)?;
+        Ok(())
+    }
+}
+
+impl MessageQueue for KafkaMQ {
+    type Options = KafkaOptions;
+    type Commands = NoCommands;
+    type PublishOptions = KafkaPublishOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = KafkaPublisher;
+
+    async fn connect(addr: Option<&str>, options: &KafkaOptions) -> anyhow::Result<Self> {
+        let servers = addr.unwrap_or("localhost:9092");
+        let servers = servers.strip_prefix("kafka://").unwrap_or(servers).trim_end_matches('/').to_owned();
+        // librdkafka connects in the background, errors show up on first use
+        let producer = options.producer(&servers, None)
+            .map_err(|err| anyhow!("failed to create client: {}", err))?;
+        Ok(Self { servers, producer, options: options.clone() })
+    }
+
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
+        let producer = self.producer.clone();
+        let (metadata, cluster_id) = tokio::task::spawn_blocking(move || {
+            let client = producer.client();
+            client.fetch_metadata(None, TIMEOUT).map(|metadata| (metadata, client.fetch_cluster_id(TIMEOUT)))
+        }).await?.map_err(|err| anyhow!("failed to fetch metadata: {}", err))?;
+
+        let mut info = vec![];
+        info.push(("Bootstrap Servers", self.servers.clone()));
+        info.push(("Client Version", format!("librdkafka {}", rdkafka::util::get_rdkafka_version().1)));
+        info.push(("Cluster ID", cluster_id.unwrap_or_else(|| "-".to_string())));
+        info.push(("Metadata From", format!("{} (id {})", metadata.orig_broker_name(), metadata.orig_broker_id())));
+        for broker in metadata.brokers() {
+            info.push(("Broker", format!("{} {}:{}", broker.id(), broker.host(), broker.port())));
+        }
+        let topics = metadata.topics().iter().filter(|t| !t.name().starts_with("__")).count();
+        let partitions: usize = metadata.topics().iter().map(|t| t.partitions().len()).sum();
+        info.push(("Topics", topics.to_string()));
+        info.push(("Partitions", partitions.to_string()));
+
+        Ok(format_info(&info, json))
+    }
+
+    async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        send(&self.producer, topic, None, self.options.produce_partition()?, headers, payload).await
+    }
+
+    async fn publisher(&self, topic: &str, options: &KafkaPublishOptions) -> anyhow::Result<KafkaPublisher> {
+        // partitioner is set on the producer, the shared one uses librdkafka's default
+        let producer = match options.partitioner {
+            Some(partitioner) => self.options.producer(&self.servers, Some(partitioner))
+                .map_err(|err| anyhow!("failed to create producer: {}", err))?,
+            None => self.producer.clone(),
+        };
+        Ok(KafkaPublisher {
+            producer,
+            topic: topic.to_owned(),
+            key: options.key.clone(),
+            partition: self.options.produce_partition()?,
+        })
+    }
+
+    fn subscribe(&self, topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        let topic = topic.to_owned();
+
+        async_stream::try_stream! {
+            let consumer = self.consumer()?;
+            let partitions = self.options.partition.clone();
+            let offset_reset = self.options.offset_reset;
+            let consumer = if partitions.is_empty() && !matches!(offset_reset, OffsetReset::Timestamp(_)) {
+                consumer.subscribe(&[&topic])?;
+                consumer
+            } else {
+                // metadata and offset lookups are blocking
+                let topic = topic.clone();
+                tokio::task::spawn_blocking(move || {
+                    Self::assign(&consumer, &topic, &partitions, offset_reset).map(|_| consumer)
+                }).await??
+            };
+
+            loop {
+                let message = consumer.recv().await
+                    .map_err(|err| anyhow!("failed to receive: {}", err))?;
+                let frame = message_to_frame(&message);
+                yield frame;
+            }
+        }
+    }
+
+    async fn request(&self, _topic: &str, _headers: &[(String, String)], _payload: &[u8]) -> anyhow::Result<Frame> {
+        bail!("request is not supported by kafka");
+    }
+
+    async fn command(_addr: Option<&str>, _options: &KafkaOptions, command: NoCommands) -> anyhow::Result<()> {
+        match command {}
+    }
+}
+
+pub async fn run(args: impl Iterator<Item = String>) {
+    crate::cli::run::<KafkaMQ>(args).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_reset() {
+        assert_eq!(parse_offset_reset("earliest"), Ok(OffsetReset::Earliest));
+        assert_eq!(parse_offset_reset("1700000000000"), Ok(OffsetReset::Timestamp(1700000000000)));
+        let Ok(OffsetReset::Timestamp(ms)) = parse_offset_reset("1h") else { panic!() };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+        assert!((now - 3_600_000 - ms).abs() < 1000);
+        assert!(parse_offset_reset("first").is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn produce_consume() {
+        use futures_util::StreamExt;
+
+        let cluster = rdkafka::mocking::MockCluster::new(1).unwrap();
+        cluster.create_topic("events", 2, 1).unwrap();
+        let options = KafkaOptions {
+            group: None,
+            offset_reset: OffsetReset::Earliest,
+            partition: vec![1],
+            cfg: vec![],
+        };
+        let mq = KafkaMQ::connect(Some(&cluster.bootstrap_servers()), &options).await.unwrap();
+        let publish_options = KafkaPublishOptions { key: Some("user-1".to_string()), partitioner: Some(Partitioner::Murmur2Random) };
+        let publisher = mq.publisher("events", &publish_options).await.unwrap();
+        publisher.publish(&[("lang".to_string(), "en".to_string())], b"hello").await.unwrap();
+
+        let frame = tokio::time::timeout(TIMEOUT, Box::pin(mq.subscribe("events", &NoOptions {})).next()).await
+            .unwrap().unwrap().unwrap();
+        assert_eq!(frame.payload, b"hello");
+        assert_eq!(frame.headers["Partition"], ["1"]);
+        assert_eq!(frame.headers["Offset"], ["0"]);
+        assert_eq!(frame.headers["Key"], ["user-1"]);
+        assert_eq!(frame.headers["lang"], ["en"]);
+        assert!(frame.headers["Timestamp"][0].ends_with("(create time)"));
+    }
+}
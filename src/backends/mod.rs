@@ -1,8 +1,44 @@
+#[cfg(feature = "backend-amqp")]
+pub mod amqp;
+
 #[cfg(feature = "backend-centrifuge")]
 pub mod centrifuge;
 
+#[cfg(feature = "backend-centrifuge")]
+pub mod cfapi;
+
+#[cfg(feature = "backend-http")]
+pub mod http;
+
+#[cfg(feature = "backend-kafka")]
+pub mod kafka;
+
+#[cfg(feature = "backend-mqttsn")]
+pub mod mqttsn;
+
 #[cfg(feature = "backend-nats")]
 pub mod nats;
 
+#[cfg(feature = "backend-postgres")]
+pub mod postgres;
+
+#[cfg(feature = "backend-redis")]
+pub mod redis;
+
+#[cfg(feature = "backend-http")]
+pub mod sse;
+
+#[cfg(feature = "backend-stomp")]
+pub mod stomp;
+
+#[cfg(feature = "backend-udp")]
+pub mod udp;
+
+#[cfg(feature = "backend-ws")]
+pub mod ws;
+
 #[cfg(feature = "backend-zenoh")]
 pub mod zenoh;
+
+#[cfg(feature = "backend-zmq")]
+pub mod zmq;
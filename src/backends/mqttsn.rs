@@ -0,0 +1,715 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use clap::{Args, ValueEnum};
+use futures_util::Stream;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+
+use crate::cli::{NoCommands, NoOptions, parse_duration};
+use crate::mqtrait::{Frame, MessageQueue, Publisher};
+use crate::utils::format_info;
+
+// gateway acks are waited for this many times, resending in between
+const RETRIES: usize = 3;
+
+// msg ids of the last QoS 1 messages acked by sub, resent with DUP when the PUBACK got lost
+const ACKED_IDS: usize = 16;
+
+const FLAG_DUP: u8 = 0x80;
+const FLAG_RETAIN: u8 = 0x10;
+const FLAG_CLEAN_SESSION: u8 = 0x04;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum TopicIdType {
+    /// topic name, registered with the gateway
+    Normal,
+    /// numeric topic id configured on the gateway
+    Predefined,
+    /// two character topic name
+    Short,
+}
+
+impl TopicIdType {
+    fn flags(&self) -> u8 {
+        match self {
+            Self::Normal => 0,
+            Self::Predefined => 1,
+            Self::Short => 2,
+        }
+    }
+
+    fn from_flags(flags: u8) -> Option<Self> {
+        match flags & 0x03 {
+            0 => Some(Self::Normal),
+            1 => Some(Self::Predefined),
+            2 => Some(Self::Short),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "MQTT-SN Options")]
+pub struct MqttSnOptions {
+    #[arg(global = true, long, help = "QoS of published messages (-1 publishes without connecting) and subscriptions", default_value = "0", allow_negative_numbers = true, value_parser = clap::value_parser!(i8).range(-1..=1))]
+    qos: i8,
+    #[arg(global = true, long, help = "how the channel is sent to the gateway", default_value = "normal")]
+    topic_id_type: TopicIdType,
+    #[arg(global = true, long, help = "client id [default: mqcat-<pid>]")]
+    client_id: Option<String>,
+    #[arg(global = true, long, help = "keep alive duration, sub pings the gateway within it", default_value = "60s", value_parser = parse_duration)]
+    keep_alive: Duration,
+    #[arg(global = true, long, help = "wait for each gateway ack, retried 3 times", default_value = "5s", value_parser = parse_duration)]
+    timeout: Duration,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "MQTT-SN Publish Options")]
+pub struct MqttSnPublishOptions {
+    #[arg(long, help = "publish retained messages")]
+    retain: bool,
+}
+
+fn qos_flags(qos: i8) -> u8 {
+    match qos {
+        -1 => 0x60,
+        1 => 0x20,
+        2 => 0x40,
+        _ => 0,
+    }
+}
+
+fn flags_qos(flags: u8) -> i8 {
+    match (flags >> 5) & 0x03 {
+        1 => 1,
+        2 => 2,
+        3 => -1,
+        _ => 0,
+    }
+}
+
+fn return_code(code: u8) -> &'static str {
+    match code {
+        0 => "accepted",
+        1 => "rejected: congestion",
+        2 => "rejected: invalid topic id",
+        3 => "rejected: not supported",
+        _ => "rejected: unknown return code",
+    }
+}
+
+/// MQTT-SN 1.2 messages used by the client, others are kept as their type.
+#[derive(Debug, Clone, PartialEq)]
+enum Packet {
+    Connect { flags: u8, duration: u16, client_id: String },
+    Connack { code: u8 },
+    Register { topic_id: u16, msg_id: u16, topic: String },
+    Regack { topic_id: u16, msg_id: u16, code: u8 },
+    Publish { flags: u8, topic_id: u16, msg_id: u16, data: Vec<u8> },
+    Puback { topic_id: u16, msg_id: u16, code: u8 },
+    // topic is the name, or two bytes of predefined id / short name
+    Subscribe { flags: u8, msg_id: u16, topic: Vec<u8> },
+    Suback { flags: u8, topic_id: u16, msg_id: u16, code: u8 },
+    Pingreq,
+    Pingresp,
+    Disconnect,
+    Other(u8),
+}
+
+impl Packet {
+    fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        let mut body = vec![];
+        let kind = match self {
+            Self::Connect { flags, duration, client_id } => {
+                body.extend([*flags, 0x01]);
+                body.extend(duration.to_be_bytes());
+                body.extend(client_id.as_bytes());
+                0x04
+            }
+            Self::Connack { code } => {
+                body.push(*code);
+                0x05
+            }
+            Self::Register { topic_id, msg_id, topic } => {
+                body.extend(topic_id.to_be_bytes());
+                body.extend(msg_id.to_be_bytes());
+                body.extend(topic.as_bytes());
+                0x0A
+            }
+            Self::Regack { topic_id, msg_id, code } => {
+                body.extend(topic_id.to_be_bytes());
+                body.extend(msg_id.to_be_bytes());
+                body.push(*code);
+                0x0B
+            }
+            Self::Publish { flags, topic_id, msg_id, data } => {
+                body.push(*flags);
+                body.extend(topic_id.to_be_bytes());
+                body.extend(msg_id.to_be_bytes());
+                body.extend(data);
+                0x0C
+            }
+            Self::Puback { topic_id, msg_id, code } => {
+                body.extend(topic_id.to_be_bytes());
+                body.extend(msg_id.to_be_bytes());
+                body.push(*code);
+                0x0D
+            }
+            Self::Subscribe { flags, msg_id, topic } => {
+                body.push(*flags);
+                body.extend(msg_id.to_be_bytes());
+                body.extend(topic);
+                0x12
+            }
+            Self::Suback { flags, topic_id, msg_id, code } => {
+                body.push(*flags);
+                body.extend(topic_id.to_be_bytes());
+                body.extend(msg_id.to_be_bytes());
+                body.push(*code);
+                0x13
+            }
+            Self::Pingreq => 0x16,
+            Self::Pingresp => 0x17,
+            Self::Disconnect => 0x18,
+            Self::Other(kind) => *kind,
+        };
+
+        // length includes itself, three byte form for messages over 255 bytes
+        let mut packet = match body.len() + 2 {
+            len if len <= 255 => vec![len as u8],
+            len => {
+                let Ok(len) = u16::try_from(len + 2) else {
+                    bail!("message of {} bytes exceeds maximum packet size", body.len());
+                };
+                let mut length = vec![0x01];
+                length.extend(len.to_be_bytes());
+                length
+            }
+        };
+        packet.push(kind);
+        packet.extend(body);
+        Ok(packet)
+    }
+
+    fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        let (len, header) = match data.first() {
+            Some(0x01) if data.len() >= 3 => (u16::from_be_bytes([data[1], data[2]]) as usize, 3),
+            Some(&len) => (len as usize, 1),
+            None => bail!("empty message"),
+        };
+        if len != data.len() || len <= header {
+            bail!("invalid message length {} of {} byte datagram", len, data.len());
+        }
+        let kind = data[header];
+        let body = &data[header + 1..];
+        let short = || anyhow!("message 0x{:02x} is too short", kind);
+        let u16_at = |i: usize| body.get(i..i + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or_else(short);
+        let u8_at = |i: usize| body.get(i).copied().ok_or_else(short);
+
+        Ok(match kind {
+            0x04 => Self::Connect { flags: u8_at(0)?, duration: u16_at(2)?, client_id: String::from_utf8_lossy(&body[4..]).into_owned() },
+            0x05 => Self::Connack { code: u8_at(0)? },
+            0x0A => Self::Register { topic_id: u16_at(0)?, msg_id: u16_at(2)?, topic: String::from_utf8_lossy(&body[4..]).into_owned() },
+            0x0B => Self::Regack { topic_id: u16_at(0)?, msg_id: u16_at(2)?, code: u8_at(4)? },
+            0x0C => Self::Publish { flags: u8_at(0)?, topic_id: u16_at(1)?, msg_id: u16_at(3)?, data: body[5..].to_vec() },
+            0x0D => Self::Puback { topic_id: u16_at(0)?, msg_id: u16_at(2)?, code: u8_at(4)? },
+            0x12 => Self::Subscribe { flags: u8_at(0)?, msg_id: u16_at(1)?, topic: body[3..].to_vec() },
+            0x13 => Self::Suback { flags: u8_at(0)?, topic_id: u16_at(1)?, msg_id: u16_at(3)?, code: u8_at(5)? },
+            0x16 => Self::Pingreq,
+            0x17 => Self::Pingresp,
+            0x18 => Self::Disconnect,
+            kind => Self::Other(kind),
+        })
+    }
+}
+
+/// Channel as topic field of SUBSCRIBE, or topic id for the other topic id types.
+fn encode_topic(topic: &str, id_type: TopicIdType) -> anyhow::Result<Vec<u8>> {
+    match id_type {
+        TopicIdType::Normal => Ok(topic.as_bytes().to_vec()),
+        TopicIdType::Predefined => {
+            let id: u16 = topic.parse()
+                .map_err(|_| anyhow!("predefined topic id must be a number, got \"{}\"", topic))?;
+            Ok(id.to_be_bytes().to_vec())
+        }
+        TopicIdType::Short => match topic.len() {
+            2 => Ok(topic.as_bytes().to_vec()),
+            _ => bail!("short topic name must be 2 characters, got \"{}\"", topic),
+        },
+    }
+}
+
+fn client_id(options: &MqttSnOptions) -> String {
+    options.client_id.clone().unwrap_or_else(|| format!("mqcat-{}", std::process::id()))
+}
+
+struct Session {
+    socket: UdpSocket,
+    timeout: Duration,
+    msg_id: u16,
+    // received while waiting for an ack, handed out by recv first
+    pending: VecDeque<Packet>,
+    connected: bool,
+}
+
+impl Session {
+    async fn open(addr: SocketAddr, options: &MqttSnOptions, connect: bool) -> anyhow::Result<Self> {
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        let mut session = Self { socket, timeout: options.timeout, msg_id: 0, pending: VecDeque::new(), connected: false };
+        if !connect {
+            return Ok(session);
+        }
+
+        let client_id = client_id(options);
+        let connect = Packet::Connect {
+            flags: FLAG_CLEAN_SESSION,
+            duration: options.keep_alive.as_secs().min(u16::MAX as u64) as u16,
+            client_id,
+        };
+        let Packet::Connack { code } = session.exchange(connect, |packet| matches!(packet, Packet::Connack { .. })).await? else {
+            unreachable!();
+        };
+        if code != 0 {
+            bail!("connection {}", return_code(code));
+        }
+        session.connected = true;
+        Ok(session)
+    }
+
+    fn next_msg_id(&mut self) -> u16 {
+        self.msg_id = self.msg_id.checked_add(1).unwrap_or(1);
+        self.msg_id
+    }
+
+    async fn send(&self, packet: &Packet) -> anyhow::Result<()> {
+        self.socket.send(&packet.encode()?).await?;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> anyhow::Result<Packet> {
+        if let Some(packet) = self.pending.pop_front() {
+            return Ok(packet);
+        }
+        self.recv_socket().await
+    }
+
+    /// Sends the packet until the gateway answers with a matching one, keeping messages received meanwhile.
+    async fn exchange(&mut self, mut packet: Packet, matches: impl Fn(&Packet) -> bool) -> anyhow::Result<Packet> {
+        for attempt in 0..RETRIES {
+            if attempt > 0 {
+                log::debug!("no reply from gateway, resending");
+                if let Packet::Publish { flags, .. } = &mut packet {
+                    *flags |= FLAG_DUP;
+                }
+            }
+            self.send(&packet).await?;
+            let deadline = tokio::time::Instant::now() + self.timeout;
+            loop {
+                let reply = match tokio::time::timeout_at(deadline, self.recv_socket()).await {
+                    Ok(reply) => reply?,
+                    Err(_) => break,
+                };
+                match reply {
+                    reply if matches(&reply) => return Ok(reply),
+                    Packet::Disconnect => bail!("disconnected by gateway"),
+                    reply @ (Packet::Publish { .. } | Packet::Register { .. }) => self.pending.push_back(reply),
+                    reply => log::debug!("skipping {:?}", reply),
+                }
+            }
+        }
+        bail!("no reply from gateway after {} attempts", RETRIES)
+    }
+
+    // reads the socket only, pending messages are left for recv
+    async fn recv_socket(&mut self) -> anyhow::Result<Packet> {
+        let mut buf = vec![0; 65535];
+        let len = self.socket.recv(&mut buf).await?;
+        Packet::decode(&buf[..len])
+    }
+
+    /// Topic id of the channel, registering topic names with the gateway.
+    async fn topic_id(&mut self, topic: &str, id_type: TopicIdType) -> anyhow::Result<u16> {
+        if id_type != TopicIdType::Normal {
+            let id = encode_topic(topic, id_type)?;
+            return Ok(u16::from_be_bytes([id[0], id[1]]));
+        }
+        let msg_id = self.next_msg_id();
+        let register = Packet::Register { topic_id: 0, msg_id, topic: topic.to_owned() };
+        let reply = self.exchange(register, |packet| matches!(packet, Packet::Regack { msg_id: id, .. } if *id == msg_id)).await?;
+        let Packet::Regack { topic_id, code, .. } = reply else {
+            unreachable!();
+        };
+        if code != 0 {
+            bail!("register {}", return_code(code));
+        }
+        log::debug!("registered \"{}\" as topic id {}", topic, topic_id);
+        Ok(topic_id)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if self.connected && let Ok(disconnect) = Packet::Disconnect.encode() {
+            let _ = self.socket.try_send(&disconnect);
+        }
+    }
+}
+
+struct MqttSnMQ {
+    url: String,
+    options: MqttSnOptions,
+}
+
+impl MqttSnMQ {
+    async fn resolve(&self) -> anyhow::Result<SocketAddr> {
+        let address = self.url.strip_prefix("udp://")
+            .ok_or_else(|| anyhow!("url must be mqttsn+udp://host:port"))?
+            .trim_end_matches('/');
+        tokio::net::lookup_host(address).await
+            .map_err(|err| anyhow!("failed to resolve {}: {}", address, err))?
+            .next()
+            .ok_or_else(|| anyhow!("failed to resolve {}", address))
+    }
+}
+
+pub struct MqttSnPublisher {
+    session: Mutex<Session>,
+    topic_id: u16,
+    flags: u8,
+    qos: i8,
+}
+
+impl Publisher for MqttSnPublisher {
+    async fn publish(&self, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        if headers.len() > 0 {
+            log::warn!("setting headers is not supported by mqtt-sn");
+        }
+        let mut session = self.session.lock().await;
+        let msg_id = if self.qos == 1 { session.next_msg_id() } else { 0 };
+        let publish = Packet::Publish { flags: self.flags, topic_id: self.topic_id, msg_id, data: payload.to_vec() };
+        if self.qos < 1 {
+            return session.send(&publish).await
+                .map_err(|err| anyhow!("failed to publish: {}", err));
+        }
+
+        let reply = session.exchange(publish, |packet| matches!(packet, Packet::Puback { msg_id: id, .. } if *id == msg_id)).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        if let Packet::Puback { code, .. } = reply && code != 0 {
+            bail!("failed to publish: {}", return_code(code));
+        }
+        Ok(())
+    }
+}
+
+impl MessageQueue for MqttSnMQ {
+    type Options = MqttSnOptions;
+    type Commands = NoCommands;
+    type PublishOptions = MqttSnPublishOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = MqttSnPublisher;
+
+    async fn connect(addr: Option<&str>, options: &MqttSnOptions) -> anyhow::Result<Self> {
+        let url = addr.unwrap_or("udp://localhost:1884").to_owned();
+        Ok(Self { url, options: options.clone() })
+    }
+
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
+        let addr = self.resolve().await?;
+        let mut session = Session::open(addr, &self.options, true).await
+            .map_err(|err| anyhow!("failed to connect: {}", err))?;
+        let started = Instant::now();
+        session.exchange(Packet::Pingreq, |packet| *packet == Packet::Pingresp).await
+            .map_err(|err| anyhow!("ping failed: {}", err))?;
+
+        let info = vec![
+            ("URL", format!("mqttsn+{}", self.url)),
+            ("Gateway", addr.to_string()),
+            ("Client ID", client_id(&self.options)),
+            ("Ping", format!("{:?}", started.elapsed())),
+        ];
+        Ok(format_info(&info, json))
+    }
+
+    async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        self.publisher(topic, &MqttSnPublishOptions { retain: false }).await?.publish(headers, payload).await
+    }
+
+    async fn publisher(&self, topic: &str, options: &MqttSnPublishOptions) -> anyhow::Result<MqttSnPublisher> {
+        let id_type = self.options.topic_id_type;
+        // QoS -1 is sent without a connection, so there is nothing to register with
+        if self.options.qos == -1 && id_type == TopicIdType::Normal {
+            bail!("QoS -1 needs a predefined or short topic id (--topic-id-type)");
+        }
+        let addr = self.resolve().await?;
+        let mut session = Session::open(addr, &self.options, self.options.qos != -1).await
+            .map_err(|err| anyhow!("failed to connect: {}", err))?;
+        let topic_id = session.topic_id(topic, id_type).await
+            .map_err(|err| anyhow!("failed to register topic: {}", err))?;
+
+        let mut flags = qos_flags(self.options.qos) | id_type.flags();
+        if options.retain {
+            flags |= FLAG_RETAIN;
+        }
+        Ok(MqttSnPublisher { session: Mutex::new(session), topic_id, flags, qos: self.options.qos })
+    }
+
+    fn subscribe(&self, topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        let topic = topic.to_owned();
+
+        async_stream::try_stream! {
+            if self.options.qos == -1 {
+                Err(anyhow!("QoS -1 is only supported by pub"))?;
+            }
+            let id_type = self.options.topic_id_type;
+            let addr = self.resolve().await?;
+            let mut session = Session::open(addr, &self.options, true).await
+                .map_err(|err| anyhow!("failed to connect: {}", err))?;
+
+            let msg_id = session.next_msg_id();
+            let subscribe = Packet::Subscribe { flags: qos_flags(self.options.qos) | id_type.flags(), msg_id, topic: encode_topic(&topic, id_type)? };
+            let reply = session.exchange(subscribe, |packet| matches!(packet, Packet::Suback { msg_id: id, .. } if *id == msg_id)).await
+                .map_err(|err| anyhow!("failed to subscribe: {}", err))?;
+            let Packet::Suback { topic_id, code, .. } = reply else {
+                unreachable!();
+            };
+            if code != 0 {
+                Err(anyhow!("subscription {}", return_code(code)))?;
+            }
+
+            // names of normal topic ids, wildcard subscriptions get them through REGISTER
+            let mut topics = HashMap::new();
+            if id_type == TopicIdType::Normal && topic_id != 0 {
+                topics.insert(topic_id, topic.clone());
+            }
+
+            let keep_alive = self.options.keep_alive / 2;
+            let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + keep_alive, keep_alive.max(Duration::from_secs(1)));
+            // set while a PINGREQ waits for its PINGRESP
+            let mut pong_deadline = None;
+            let mut acked = VecDeque::new();
+            loop {
+                let pong_timeout = async move {
+                    match pong_deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline).await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let packet = tokio::select! {
+                    packet = session.recv() => Some(packet),
+                    _ = ping.tick(), if !keep_alive.is_zero() => None,
+                    _ = pong_timeout => Some(Err(anyhow!("no ping response from gateway within {:?}", self.options.timeout))),
+                };
+                let Some(packet) = packet else {
+                    session.send(&Packet::Pingreq).await?;
+                    pong_deadline.get_or_insert(tokio::time::Instant::now() + self.options.timeout);
+                    continue;
+                };
+
+                match packet? {
+                    Packet::Register { topic_id, msg_id, topic } => {
+                        log::debug!("gateway registered \"{}\" as topic id {}", topic, topic_id);
+                        topics.insert(topic_id, topic);
+                        session.send(&Packet::Regack { topic_id, msg_id, code: 0 }).await?;
+                    }
+                    Packet::Publish { flags, topic_id, msg_id, data } => {
+                        let qos = flags_qos(flags);
+                        if qos == 1 {
+                            session.send(&Packet::Puback { topic_id, msg_id, code: 0 }).await?;
+                            if flags & FLAG_DUP != 0 && acked.contains(&msg_id) {
+                                log::debug!("skipping resent message {}", msg_id);
+                                continue;
+                            }
+                            if acked.len() == ACKED_IDS {
+                                acked.pop_front();
+                            }
+                            acked.push_back(msg_id);
+                        }
+                        let id_type = TopicIdType::from_flags(flags).unwrap_or(TopicIdType::Normal);
+                        let name = match id_type {
+                            TopicIdType::Normal => topics.get(&topic_id).cloned().unwrap_or_else(|| topic_id.to_string()),
+                            TopicIdType::Predefined => topic_id.to_string(),
+                            TopicIdType::Short => String::from_utf8_lossy(&topic_id.to_be_bytes()).into_owned(),
+                        };
+
+                        let mut frame = Frame {
+                            topic: name,
+                            headers: Default::default(),
+                            payload: data,
+                        };
+                        frame.headers.insert("Topic-Id".to_string(), vec![topic_id.to_string()]);
+                        frame.headers.insert("Topic-Id-Type".to_string(), vec![format!("{:?}", id_type).to_lowercase()]);
+                        frame.headers.insert("QoS".to_string(), vec![qos.to_string()]);
+                        if flags & FLAG_RETAIN != 0 {
+                            frame.headers.insert("Retain".to_string(), vec!["true".to_string()]);
+                        }
+                        if qos == 1 {
+                            frame.headers.insert("Msg-Id".to_string(), vec![msg_id.to_string()]);
+                        }
+                        yield frame;
+                    }
+                    Packet::Pingreq => session.send(&Packet::Pingresp).await?,
+                    Packet::Pingresp => pong_deadline = None,
+                    Packet::Disconnect => Err(anyhow!("disconnected by gateway"))?,
+                    packet => log::debug!("skipping {:?}", packet),
+                }
+            }
+        }
+    }
+
+    async fn request(&self, _topic: &str, _headers: &[(String, String)], _payload: &[u8]) -> anyhow::Result<Frame> {
+        bail!("request is not supported by mqtt-sn");
+    }
+
+    async fn command(_addr: Option<&str>, _options: &MqttSnOptions, command: NoCommands) -> anyhow::Result<()> {
+        match command {}
+    }
+}
+
+pub async fn run(args: impl Iterator<Item = String>) {
+    crate::cli::run::<MqttSnMQ>(args).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+
+    use super::*;
+
+    fn options(qos: i8) -> MqttSnOptions {
+        MqttSnOptions {
+            qos,
+            topic_id_type: TopicIdType::Normal,
+            client_id: Some("test".to_string()),
+            keep_alive: Duration::from_secs(60),
+            timeout: Duration::from_secs(2),
+        }
+    }
+
+    #[test]
+    fn packets() {
+        let publish = Packet::Publish { flags: qos_flags(-1) | TopicIdType::Predefined.flags(), topic_id: 17, msg_id: 0, data: b"21.5".to_vec() };
+        assert_eq!(publish.encode().unwrap(), [11, 0x0C, 0x61, 0, 17, 0, 0, b'2', b'1', b'.', b'5']);
+        assert_eq!(Packet::decode(&publish.encode().unwrap()).unwrap(), publish);
+
+        // long messages use three byte length
+        let long = Packet::Publish { flags: 0, topic_id: 1, msg_id: 0, data: vec![7; 300] };
+        let data = long.encode().unwrap();
+        assert_eq!(&data[..4], [0x01, 0x01, 0x35, 0x0C]);
+        assert_eq!(Packet::decode(&data).unwrap(), long);
+        // length field is two bytes
+        assert!(Packet::Publish { flags: 0, topic_id: 1, msg_id: 0, data: vec![7; 65530] }.encode().is_err());
+
+        assert!(Packet::decode(&[5, 0x0C, 0]).is_err());
+        assert_eq!(encode_topic("ab", TopicIdType::Short).unwrap(), b"ab");
+        assert!(encode_topic("temp", TopicIdType::Predefined).is_err());
+    }
+
+    #[tokio::test]
+    async fn gateway_session() {
+        let gateway = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("udp://{}", gateway.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut buf = vec![0; 1024];
+            let mut recv = async || {
+                let (len, peer) = gateway.recv_from(&mut buf).await.unwrap();
+                (Packet::decode(&buf[..len]).unwrap(), peer)
+            };
+            let mut received = vec![];
+
+            // publisher: connect, register, QoS 1 publish
+            let (connect, peer) = recv().await;
+            received.push(connect);
+            gateway.send_to(&Packet::Connack { code: 0 }.encode().unwrap(), peer).await.unwrap();
+            let (register, _) = recv().await;
+            let Packet::Register { msg_id, .. } = register.clone() else { panic!("{:?}", register) };
+            received.push(register);
+            gateway.send_to(&Packet::Regack { topic_id: 5, msg_id, code: 0 }.encode().unwrap(), peer).await.unwrap();
+            let (publish, _) = recv().await;
+            let Packet::Publish { msg_id, .. } = publish.clone() else { panic!("{:?}", publish) };
+            received.push(publish);
+            gateway.send_to(&Packet::Puback { topic_id: 5, msg_id, code: 0 }.encode().unwrap(), peer).await.unwrap();
+            assert_eq!(recv().await.0, Packet::Disconnect);
+
+            // subscriber: wildcard subscription, topic registered by gateway before the message
+            let (_, peer) = recv().await;
+            gateway.send_to(&Packet::Connack { code: 0 }.encode().unwrap(), peer).await.unwrap();
+            let (subscribe, _) = recv().await;
+            let Packet::Subscribe { msg_id, .. } = subscribe.clone() else { panic!("{:?}", subscribe) };
+            received.push(subscribe);
+            gateway.send_to(&Packet::Suback { flags: 0x20, topic_id: 0, msg_id, code: 0 }.encode().unwrap(), peer).await.unwrap();
+            gateway.send_to(&Packet::Register { topic_id: 9, msg_id: 1, topic: "sensors/temp".to_string() }.encode().unwrap(), peer).await.unwrap();
+            received.push(recv().await.0);
+            gateway.send_to(&Packet::Publish { flags: 0x20, topic_id: 9, msg_id: 2, data: b"21.5".to_vec() }.encode().unwrap(), peer).await.unwrap();
+            received.push(recv().await.0);
+            received
+        });
+
+        let mq = MqttSnMQ::connect(Some(&url), &options(1)).await.unwrap();
+        mq.publish("sensors/temp", &[], b"21.5").await.unwrap();
+        let frame = Box::pin(mq.subscribe("sensors/+", &NoOptions {})).next().await.unwrap().unwrap();
+        assert_eq!(frame.topic, "sensors/temp");
+        assert_eq!(frame.payload, b"21.5");
+        assert_eq!(frame.headers["Topic-Id"], ["9"]);
+        assert_eq!(frame.headers["QoS"], ["1"]);
+
+        let received = server.await.unwrap();
+        assert_eq!(received[0], Packet::Connect { flags: FLAG_CLEAN_SESSION, duration: 60, client_id: "test".to_string() });
+        assert!(matches!(&received[1], Packet::Register { topic, .. } if topic == "sensors/temp"));
+        assert!(matches!(&received[2], Packet::Publish { flags: 0x20, topic_id: 5, data, .. } if data == b"21.5"));
+        assert!(matches!(&received[3], Packet::Subscribe { flags: 0x20, topic, .. } if topic == b"sensors/+"));
+        assert_eq!(received[4], Packet::Regack { topic_id: 9, msg_id: 1, code: 0 });
+        assert_eq!(received[5], Packet::Puback { topic_id: 9, msg_id: 2, code: 0 });
+    }
+
+    #[tokio::test]
+    async fn subscribe_resends_and_pings() {
+        let gateway = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("udp://{}", gateway.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut buf = vec![0; 1024];
+            let mut recv = async || {
+                let (len, peer) = gateway.recv_from(&mut buf).await.unwrap();
+                (Packet::decode(&buf[..len]).unwrap(), peer)
+            };
+            let (_, peer) = recv().await;
+            gateway.send_to(&Packet::Connack { code: 0 }.encode().unwrap(), peer).await.unwrap();
+            let (subscribe, _) = recv().await;
+            let Packet::Subscribe { msg_id, .. } = subscribe.clone() else { panic!("{:?}", subscribe) };
+            gateway.send_to(&Packet::Suback { flags: 0x20, topic_id: 5, msg_id, code: 0 }.encode().unwrap(), peer).await.unwrap();
+
+            // second message is resent as if the PUBACK got lost
+            let messages = [(0x20, 1, "a"), (0x20 | FLAG_DUP, 1, "a"), (0x20, 2, "b")];
+            for (flags, msg_id, data) in messages {
+                gateway.send_to(&Packet::Publish { flags, topic_id: 5, msg_id, data: data.into() }.encode().unwrap(), peer).await.unwrap();
+            }
+            let mut received = vec![];
+            for _ in 0..4 {
+                received.push(recv().await.0);
+            }
+            // PINGREQ is left unanswered
+            received
+        });
+
+        let options = MqttSnOptions { keep_alive: Duration::from_secs(2), timeout: Duration::from_millis(500), ..options(1) };
+        let mq = MqttSnMQ::connect(Some(&url), &options).await.unwrap();
+        let mut stream = Box::pin(mq.subscribe("sensors/temp", &NoOptions {}));
+        assert_eq!(stream.next().await.unwrap().unwrap().payload, b"a");
+        assert_eq!(stream.next().await.unwrap().unwrap().payload, b"b");
+        let Some(Err(err)) = stream.next().await else { panic!("stream did not fail") };
+        assert!(err.to_string().contains("no ping response"), "{}", err);
+
+        let received = server.await.unwrap();
+        assert_eq!(received[0], Packet::Puback { topic_id: 5, msg_id: 1, code: 0 });
+        assert_eq!(received[1], Packet::Puback { topic_id: 5, msg_id: 1, code: 0 });
+        assert_eq!(received[2], Packet::Puback { topic_id: 5, msg_id: 2, code: 0 });
+        assert_eq!(received[3], Packet::Pingreq);
+    }
+}
@@ -1,23 +1,219 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
 use anyhow::{anyhow, bail};
-use async_nats::{Client, HeaderMap};
+use async_nats::{Client, ClientError, ConnectOptions, Event, HeaderMap, StatusCode};
+use clap::Args;
 use futures_util::{Stream, StreamExt};
+use serde_json::Value;
+
+use crate::cli::{NoCommands, NoOptions};
+use crate::mqtrait::{ConnectionEvent, Frame, MessageQueue, TopicPublisher};
+use crate::utils::{broadcast_stream, format_table, info_to_json};
 
-use crate::mqtrait::{Frame, MessageQueue};
-use crate::utils::format_table;
+// system account endpoints queried by `info`, each server in the cluster replies separately
+const MONITORING_ENDPOINTS: [&str; 4] = ["VARZ", "CONNZ", "SUBSZ", "JSZ"];
+
+#[derive(Args, Debug)]
+#[command(next_help_heading = "NATS Options")]
+pub struct NatsOptions {
+    #[arg(global = true, long, help = "user credentials file (use system account to see server monitoring in info)")]
+    creds: Option<PathBuf>,
+    #[arg(global = true, long, help = "username")]
+    user: Option<String>,
+    #[arg(global = true, long, help = "password")]
+    password: Option<String>,
+    #[arg(global = true, long, help = "authentication token")]
+    token: Option<String>,
+}
 
 struct NatsMQ {
     url: String,
     client: Client,
+    events: tokio::sync::broadcast::Sender<ConnectionEvent>,
+}
+
+impl NatsMQ {
+    async fn ping_servers(&self, endpoint: &str) -> anyhow::Result<Vec<Value>> {
+        let inbox = self.client.new_inbox();
+        let mut subscriber = self.client.subscribe(inbox.clone()).await?;
+        let started = Instant::now();
+        self.client.publish_with_reply(format!("$SYS.REQ.SERVER.PING.{}", endpoint), inbox, "{}".into()).await?;
+        self.client.flush().await?;
+
+        // non-system accounts may get no reply at all, so the first response is awaited
+        // for a few round trips, then until servers stop responding
+        let mut responses = vec![];
+        let mut timeout = (started.elapsed() * 10).clamp(Duration::from_millis(250), Duration::from_secs(2));
+        while let Ok(Some(message)) = tokio::time::timeout(timeout, subscriber.next()).await {
+            if message.status == Some(StatusCode::NO_RESPONDERS) {
+                // not a system account
+                break;
+            }
+            responses.push(serde_json::from_slice(&message.payload)?);
+            timeout = Duration::from_millis(250);
+        }
+        let _ = subscriber.unsubscribe().await;
+
+        // keep output stable between runs
+        responses.sort_by_key(|response: &Value| field(response, &["server", "name"]));
+        Ok(responses)
+    }
+}
+
+fn field(value: &Value, path: &[&str]) -> String {
+    let mut value = value;
+    for key in path {
+        match value.get(key) {
+            Some(v) => value = v,
+            None => return "-".to_string(),
+        }
+    }
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn server_table(info: &mut Vec<(&str, String)>, server_id: &str, responses: &[Vec<Value>]) {
+    let [varz, connz, subsz, jsz] = responses else { return };
+    let find = |responses: &Vec<Value>| {
+        responses.iter().find(|r| field(r, &["server", "id"]) == server_id).cloned()
+    };
+
+    if let Some(varz) = find(varz) {
+        info.push(("", String::new()));
+        let name = field(&varz, &["server", "name"]);
+        if name != server_id {
+            info.push(("Server", format!("{} ({})", name, server_id)));
+        } else {
+            info.push(("Server", server_id.to_owned()));
+        }
+        if let Some(cluster) = varz["server"].get("cluster") {
+            info.push(("Cluster", field(cluster, &[])));
+        }
+        info.push(("Version", field(&varz, &["data", "version"])));
+        info.push(("Uptime", field(&varz, &["data", "uptime"])));
+        info.push(("Connections", format!("{} (total {})", field(&varz, &["data", "connections"]), field(&varz, &["data", "total_connections"]))));
+        info.push(("Slow Consumers", field(&varz, &["data", "slow_consumers"])));
+        info.push(("Messages In/Out", format!("{} / {}", field(&varz, &["data", "in_msgs"]), field(&varz, &["data", "out_msgs"]))));
+        info.push(("Bytes In/Out", format!("{} / {}", field(&varz, &["data", "in_bytes"]), field(&varz, &["data", "out_bytes"]))));
+        info.push(("Memory", field(&varz, &["data", "mem"])));
+    }
+
+    if let Some(subsz) = find(subsz) {
+        info.push(("Subscriptions", format!(
+            "{} (cache {}, matches {})",
+            field(&subsz, &["data", "num_subscriptions"]),
+            field(&subsz, &["data", "num_cache"]),
+            field(&subsz, &["data", "num_matches"]),
+        )));
+    }
+
+    if let Some(jsz) = find(jsz) {
+        if jsz.get("error").is_some() || jsz["data"].get("disabled") == Some(&Value::Bool(true)) {
+            info.push(("JetStream", "disabled".to_string()));
+        } else {
+            info.push(("JetStream", format!(
+                "{} streams, {} consumers, {} messages, {} bytes",
+                field(&jsz, &["data", "streams"]),
+                field(&jsz, &["data", "consumers"]),
+                field(&jsz, &["data", "messages"]),
+                field(&jsz, &["data", "bytes"]),
+            )));
+            info.push(("JetStream Memory", field(&jsz, &["data", "memory"])));
+            info.push(("JetStream Storage", field(&jsz, &["data", "storage"])));
+            info.push(("JetStream API", format!(
+                "{} calls, {} errors",
+                field(&jsz, &["data", "api", "total"]),
+                field(&jsz, &["data", "api", "errors"]),
+            )));
+        }
+    }
+
+    if let Some(connz) = find(connz) {
+        let connections = connz["data"]["connections"].as_array().cloned().unwrap_or_default();
+        for conn in connections.iter() {
+            let mut line = format!("cid={} {}:{}", field(conn, &["cid"]), field(conn, &["ip"]), field(conn, &["port"]));
+            if let Some(name) = conn.get("name").and_then(Value::as_str) {
+                line.push_str(&format!(" name=\"{}\"", name));
+            }
+            line.push_str(&format!(
+                " {} {}, subs={}, pending={}, in={}, out={}, uptime={}",
+                field(conn, &["lang"]),
+                field(conn, &["version"]),
+                field(conn, &["subscriptions"]),
+                field(conn, &["pending_bytes"]),
+                field(conn, &["in_msgs"]),
+                field(conn, &["out_msgs"]),
+                field(conn, &["uptime"]),
+            ));
+            info.push(("Connection", line));
+        }
+    }
+}
+
+/// Basic info followed by monitoring responses, per endpoint in json and per server in the table.
+fn format_monitoring(mut info: Vec<(&str, String)>, responses: Vec<Vec<Value>>, json: bool) -> anyhow::Result<String> {
+    if json {
+        let mut object = info_to_json(&info);
+        for (endpoint, response) in MONITORING_ENDPOINTS.iter().zip(responses) {
+            object.insert(endpoint.to_lowercase(), Value::Array(response));
+        }
+        let mut json = serde_json::to_string_pretty(&object)?;
+        json.push('\n');
+        return Ok(json);
+    }
+
+    if let Some(varz) = responses.first() {
+        for server_id in varz.iter().map(|r| field(r, &["server", "id"])) {
+            server_table(&mut info, &server_id, &responses);
+        }
+    }
+    Ok(format_table(&info))
 }
 
 impl MessageQueue for NatsMQ {
-    async fn connect(addr: Option<&str>) -> anyhow::Result<Self> {
+    type Options = NatsOptions;
+    type Commands = NoCommands;
+    type PublishOptions = NoOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = TopicPublisher<'a, Self>;
+
+    async fn connect(addr: Option<&str>, options: &NatsOptions) -> anyhow::Result<Self> {
         let url = addr.unwrap_or("nats://localhost:4222").to_owned();
-        let client = async_nats::connect(&url).await?;
-        Ok(Self { url, client })
+        let mut connect_options = ConnectOptions::new();
+        if let Some(creds) = &options.creds {
+            connect_options = connect_options.credentials_file(creds).await
+                .map_err(|err| anyhow!("failed to read credentials file {:?}: {}", creds, err))?;
+        }
+        if let Some(user) = &options.user {
+            connect_options = connect_options.user_and_password(user.clone(), options.password.clone().unwrap_or_default());
+        }
+        if let Some(token) = &options.token {
+            connect_options = connect_options.token(token.clone());
+        }
+        let (events, _) = tokio::sync::broadcast::channel(16);
+        let events_tx = events.clone();
+        connect_options = connect_options.event_callback(move |event| {
+            let events_tx = events_tx.clone();
+            async move {
+                log::debug!("event: {}", event);
+                let event = match event {
+                    Event::Connected => ConnectionEvent::Connected,
+                    Event::Disconnected => ConnectionEvent::Disconnected { code: None, reason: "connection lost".to_string() },
+                    Event::ClientError(ClientError::Other(error)) => ConnectionEvent::ReconnectFailed { error },
+                    _ => return,
+                };
+                let _ = events_tx.send(event);
+            }
+        });
+        let client = connect_options.connect(&url).await?;
+        Ok(Self { url, client, events })
     }
 
-    async fn info(&self) -> anyhow::Result<String> {
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
         let mut info = vec![];
         info.push(("URL", self.url.clone()));
 
@@ -39,7 +235,25 @@ impl MessageQueue for NatsMQ {
         info.push(("Maximum Payload", server_info.max_payload.to_string()));
         info.push(("Timeout", if let Some(timeout) = self.client.timeout() { format!("{:?}", timeout) } else { "None".to_string() }));
 
-        Ok(format_table(&info))
+        // only available when connected with system account credentials
+        let mut responses = vec![];
+        for endpoint in MONITORING_ENDPOINTS {
+            match self.ping_servers(endpoint).await {
+                Ok(response) if response.is_empty() && endpoint == "VARZ" => {
+                    log::debug!("no response from $SYS.REQ.SERVER.PING, not a system account");
+                    break;
+                }
+                Ok(response) => responses.push(response),
+                Err(err) => {
+                    // basic info is still shown, without partial server monitoring
+                    log::debug!("failed to query $SYS.REQ.SERVER.PING.{}: {}", endpoint, err);
+                    responses.clear();
+                    break;
+                }
+            }
+        }
+
+        format_monitoring(info, responses, json)
     }
 
     async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
@@ -56,7 +270,11 @@ impl MessageQueue for NatsMQ {
         Ok(())
     }
 
-    fn subscribe(&self, topic: &str) -> impl Stream<Item = anyhow::Result<Frame>> {
+    async fn publisher(&self, topic: &str, _options: &NoOptions) -> anyhow::Result<TopicPublisher<'_, Self>> {
+        Ok(TopicPublisher::new(self, topic))
+    }
+
+    fn subscribe(&self, topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
         let subscriber = self.client.subscribe(topic.to_owned());
 
         async_stream::try_stream! {
@@ -99,8 +317,113 @@ impl MessageQueue for NatsMQ {
         }
         Ok(frame)
     }
+
+    async fn command(_addr: Option<&str>, _options: &NatsOptions, command: NoCommands) -> anyhow::Result<()> {
+        match command {}
+    }
+
+    fn connection_events(&self) -> impl Stream<Item = ConnectionEvent> {
+        broadcast_stream(self.events.subscribe())
+    }
 }
 
 pub async fn run(args: impl Iterator<Item = String>) {
     crate::cli::run::<NatsMQ>(args).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Replies of two servers to `$SYS.REQ.SERVER.PING.<endpoint>`, the second one with
+    /// JetStream disabled, no cluster and fields missing.
+    fn responses() -> Vec<Vec<Value>> {
+        let n1 = json!({ "name": "n1", "id": "NABC", "cluster": "east" });
+        let n2 = json!({ "name": "NDEF", "id": "NDEF" });
+        vec![
+            vec![
+                json!({ "server": n1, "data": {
+                    "version": "2.10.4", "uptime": "1h2m", "connections": 2, "total_connections": 9, "slow_consumers": 0,
+                    "in_msgs": 100, "out_msgs": 80, "in_bytes": 2048, "out_bytes": 1024, "mem": 16777216,
+                } }),
+                json!({ "server": n2, "data": { "version": "2.10.4", "connections": 0 } }),
+            ],
+            vec![
+                json!({ "server": n1, "data": { "connections": [
+                    { "cid": 5, "ip": "10.0.0.7", "port": 51234, "name": "orders", "lang": "go", "version": "1.31.0",
+                      "subscriptions": 3, "pending_bytes": 0, "in_msgs": 10, "out_msgs": 4, "uptime": "5m" },
+                    { "cid": 6, "ip": "10.0.0.8", "port": 40000, "lang": "rust", "version": "0.33.0" },
+                ] } }),
+                json!({ "server": n2, "data": {} }),
+            ],
+            vec![
+                json!({ "server": n1, "data": { "num_subscriptions": 12, "num_cache": 4, "num_matches": 30 } }),
+            ],
+            vec![
+                json!({ "server": n1, "data": {
+                    "streams": 1, "consumers": 2, "messages": 500, "bytes": 65536, "memory": 0, "storage": 65536,
+                    "api": { "total": 40, "errors": 1 },
+                } }),
+                json!({ "server": n2, "error": { "code": 503, "description": "jetstream not enabled" } }),
+            ],
+        ]
+    }
+
+    #[test]
+    fn monitoring_table() {
+        let info = vec![("URL", "nats://localhost:4222".to_string())];
+        let table = format_monitoring(info, responses(), false).unwrap();
+        let lines: Vec<&str> = table.lines().map(str::trim).collect();
+        assert_eq!(lines, [
+            "URL: nats://localhost:4222",
+            "",
+            "Server: n1 (NABC)",
+            "Cluster: east",
+            "Version: 2.10.4",
+            "Uptime: 1h2m",
+            "Connections: 2 (total 9)",
+            "Slow Consumers: 0",
+            "Messages In/Out: 100 / 80",
+            "Bytes In/Out: 2048 / 1024",
+            "Memory: 16777216",
+            "Subscriptions: 12 (cache 4, matches 30)",
+            "JetStream: 1 streams, 2 consumers, 500 messages, 65536 bytes",
+            "JetStream Memory: 0",
+            "JetStream Storage: 65536",
+            "JetStream API: 40 calls, 1 errors",
+            "Connection: cid=5 10.0.0.7:51234 name=\"orders\" go 1.31.0, subs=3, pending=0, in=10, out=4, uptime=5m",
+            "Connection: cid=6 10.0.0.8:40000 rust 0.33.0, subs=-, pending=-, in=-, out=-, uptime=-",
+            "",
+            "Server: NDEF",
+            "Version: 2.10.4",
+            "Uptime: -",
+            "Connections: 0 (total -)",
+            "Slow Consumers: -",
+            "Messages In/Out: - / -",
+            "Bytes In/Out: - / -",
+            "Memory: -",
+            "JetStream: disabled",
+        ]);
+    }
+
+    #[test]
+    fn monitoring_json() {
+        let info = vec![("URL", "nats://localhost:4222".to_string())];
+        let json: Value = serde_json::from_str(&format_monitoring(info, responses(), true).unwrap()).unwrap();
+        assert_eq!(json["URL"], "nats://localhost:4222");
+        // responses are kept as sent, per endpoint
+        for (endpoint, response) in MONITORING_ENDPOINTS.iter().zip(responses()) {
+            assert_eq!(json[endpoint.to_lowercase()], Value::Array(response));
+        }
+        assert_eq!(json["jsz"][1]["error"]["code"], 503);
+
+        // without system account only basic info is shown
+        let info = vec![("URL", "nats://localhost:4222".to_string())];
+        let json: Value = serde_json::from_str(&format_monitoring(info, vec![], true).unwrap()).unwrap();
+        assert_eq!(json, json!({ "URL": "nats://localhost:4222" }));
+        let info = vec![("URL", "nats://localhost:4222".to_string())];
+        assert_eq!(format_monitoring(info, vec![], false).unwrap().trim(), "URL: nats://localhost:4222");
+    }
+}
@@ -0,0 +1,247 @@
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use clap::Args;
+use futures_util::Stream;
+use tokio::sync::mpsc;
+use tokio_postgres::{AsyncMessage, Client, Config, Notification};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::cli::{NoCommands, NoOptions, parse_duration};
+use crate::mqtrait::{Frame, MessageQueue, TopicPublisher};
+use crate::utils::format_info;
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "PostgreSQL Options")]
+pub struct PostgresOptions {
+    #[arg(global = true, long, help = "connect timeout, unless set by connect_timeout url parameter", default_value = "10s", value_parser = parse_duration)]
+    connect_timeout: Duration,
+}
+
+/// Channel names are identifiers, quoted so they keep their case and may contain any character.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn notification_to_frame(notification: &Notification) -> Frame {
+    let mut frame = Frame {
+        topic: notification.channel().to_owned(),
+        headers: Default::default(),
+        payload: notification.payload().as_bytes().to_vec(),
+    };
+    frame.headers.insert("Pid".to_string(), vec![notification.process_id().to_string()]);
+    frame
+}
+
+fn tls_connector() -> MakeRustlsConnect {
+    let mut roots = rustls::RootCertStore::empty();
+    let certs = rustls_native_certs::load_native_certs();
+    for err in certs.errors {
+        log::debug!("failed to load native certificate: {}", err);
+    }
+    roots.add_parsable_certificates(certs.certs);
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    MakeRustlsConnect::new(config)
+}
+
+struct PostgresMQ {
+    url: String,
+    config: Config,
+    client: Client,
+    // notifications of the connection above, taken by the first subscription
+    notifications: Mutex<Option<mpsc::UnboundedReceiver<anyhow::Result<Notification>>>>,
+}
+
+impl PostgresMQ {
+    /// Connects and drives the connection in the background, notifications are passed over the channel.
+    async fn open(config: &Config) -> anyhow::Result<(Client, mpsc::UnboundedReceiver<anyhow::Result<Notification>>)> {
+        // TLS is used as the url's sslmode says (prefer by default)
+        let (client, mut connection) = config.connect(tls_connector()).await?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                match std::future::poll_fn(|cx| connection.poll_message(cx)).await {
+                    Some(Ok(AsyncMessage::Notification(notification))) => {
+                        let _ = sender.send(Ok(notification));
+                    }
+                    Some(Ok(AsyncMessage::Notice(notice))) => {
+                        log::info!("{}: {}", notice.severity(), notice.message());
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => {
+                        let _ = sender.send(Err(anyhow!("connection failed: {}", err)));
+                        break;
+                    }
+                    None => {
+                        let _ = sender.send(Err(anyhow!("connection closed")));
+                        break;
+                    }
+                }
+            }
+        });
+        Ok((client, receiver))
+    }
+}
+
+impl MessageQueue for PostgresMQ {
+    type Options = PostgresOptions;
+    type Commands = NoCommands;
+    type PublishOptions = NoOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = TopicPublisher<'a, Self>;
+
+    async fn connect(addr: Option<&str>, options: &PostgresOptions) -> anyhow::Result<Self> {
+        let url = addr.unwrap_or("postgres://localhost:5432").to_owned();
+        let mut config = Config::from_str(&url)
+            .map_err(|err| anyhow!("invalid url: {}", err))?;
+        if config.get_connect_timeout().is_none() {
+            config.connect_timeout(options.connect_timeout);
+        }
+        let (client, notifications) = Self::open(&config).await
+            .map_err(|err| anyhow!("failed to connect: {:#}", err))?;
+        Ok(Self { url, config, client, notifications: Mutex::new(Some(notifications)) })
+    }
+
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
+        let row = self.client.query_one("SELECT version(), current_database()::text, current_user::text, pg_backend_pid(), \
+            pg_notification_queue_usage()::float8", &[]).await
+            .map_err(|err| anyhow!("failed to query server info: {}", err))?;
+
+        let info = vec![
+            ("URL", self.url.clone()),
+            ("Version", row.get::<_, String>(0)),
+            ("Database", row.get::<_, String>(1)),
+            ("User", row.get::<_, String>(2)),
+            ("Backend PID", row.get::<_, i32>(3).to_string()),
+            ("Notification Queue", format!("{:.2}% used", row.get::<_, f64>(4) * 100.0)),
+        ];
+        Ok(format_info(&info, json))
+    }
+
+    async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        if headers.len() > 0 {
+            log::warn!("setting headers is not supported by postgres");
+        }
+        let payload = std::str::from_utf8(payload)
+            .map_err(|_| anyhow!("failed to publish: NOTIFY payload must be text"))?;
+        self.client.execute("SELECT pg_notify($1, $2)", &[&topic, &payload]).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        Ok(())
+    }
+
+    async fn publisher(&self, topic: &str, _options: &NoOptions) -> anyhow::Result<TopicPublisher<'_, Self>> {
+        Ok(TopicPublisher::new(self, topic))
+    }
+
+    fn subscribe(&self, topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        let topic = topic.to_owned();
+
+        async_stream::try_stream! {
+            if topic.is_empty() {
+                Err(anyhow!("channel name is required for LISTEN"))?;
+            }
+            // notifications are delivered to the listening session, resubscribing after
+            // the connection failed needs a new one
+            let mut reopened = None;
+            let taken = self.notifications.lock().unwrap().take();
+            let (client, mut notifications) = match taken {
+                Some(notifications) => (&self.client, notifications),
+                None => {
+                    let (client, notifications) = Self::open(&self.config).await
+                        .map_err(|err| anyhow!("failed to connect: {:#}", err))?;
+                    (&*reopened.insert(client), notifications)
+                }
+            };
+            client.batch_execute(&format!("LISTEN {}", quote_identifier(&topic))).await
+                .map_err(|err| anyhow!("failed to listen: {}", err))?;
+            while let Some(notification) = notifications.recv().await {
+                yield notification_to_frame(&notification?);
+            }
+        }
+    }
+
+    async fn request(&self, _topic: &str, _headers: &[(String, String)], _payload: &[u8]) -> anyhow::Result<Frame> {
+        bail!("request is not supported by postgres");
+    }
+
+    async fn command(_addr: Option<&str>, _options: &PostgresOptions, command: NoCommands) -> anyhow::Result<()> {
+        match command {}
+    }
+}
+
+pub async fn run(args: impl Iterator<Item = String>) {
+    crate::cli::run::<PostgresMQ>(args).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    use futures_util::StreamExt;
+
+    use super::*;
+
+    fn write_message(stream: &mut TcpStream, tag: u8, body: &[u8]) {
+        let mut message = vec![tag];
+        message.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        stream.write_all(&message).unwrap();
+    }
+
+    fn read_message(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0; 5];
+        stream.read_exact(&mut header).unwrap();
+        let mut body = vec![0; i32::from_be_bytes(header[1..].try_into().unwrap()) as usize - 4];
+        stream.read_exact(&mut body).unwrap();
+        (header[0], body)
+    }
+
+    /// Declines TLS, accepts the startup message without authentication.
+    fn handshake(stream: &mut TcpStream) {
+        loop {
+            let mut len = [0; 4];
+            stream.read_exact(&mut len).unwrap();
+            let mut body = vec![0; i32::from_be_bytes(len) as usize - 4];
+            stream.read_exact(&mut body).unwrap();
+            if body[..4] == 80877103i32.to_be_bytes() {
+                stream.write_all(b"N").unwrap();
+                continue;
+            }
+            break;
+        }
+        write_message(stream, b'R', &0i32.to_be_bytes());
+        write_message(stream, b'Z', b"I");
+    }
+
+    #[tokio::test]
+    async fn listen_notify() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("postgres://mqcat@{}/app", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            // LISTEN runs on the connection opened by connect
+            let (mut stream, _) = listener.accept().unwrap();
+            handshake(&mut stream);
+            let (tag, query) = read_message(&mut stream);
+            write_message(&mut stream, b'C', b"LISTEN\0");
+            write_message(&mut stream, b'Z', b"I");
+            write_message(&mut stream, b'A', &[&4242i32.to_be_bytes()[..], b"Cache\0users:7\0"].concat());
+            (tag, query)
+        });
+
+        let options = PostgresOptions { connect_timeout: Duration::from_secs(5) };
+        let mq = PostgresMQ::connect(Some(&url), &options).await.unwrap();
+        let frame = Box::pin(mq.subscribe("Cache", &NoOptions {})).next().await.unwrap().unwrap();
+        assert_eq!(frame.topic, "Cache");
+        assert_eq!(frame.payload, b"users:7");
+        assert_eq!(frame.headers["Pid"], ["4242"]);
+
+        let (tag, query) = handle.join().unwrap();
+        assert_eq!(tag, b'Q');
+        assert_eq!(query, b"LISTEN \"Cache\"\0");
+    }
+}
@@ -0,0 +1,372 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use clap::Args;
+use futures_util::{Stream, StreamExt};
+use redis::aio::MultiplexedConnection;
+use redis::streams::{StreamReadOptions, StreamReadReply};
+use redis::{AsyncCommands, Client, Value};
+
+use crate::cli::{NoCommands, NoOptions};
+use crate::mqtrait::{Frame, MessageQueue, TopicPublisher};
+use crate::utils::format_info;
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Redis Options")]
+pub struct RedisOptions {
+    #[arg(global = true, long, help = "stream field that holds the payload, other fields are headers (stream mode)", default_value = "data")]
+    payload_field: String,
+    #[arg(global = true, long, help = "trim stream to approximately this many entries on publish (stream mode)")]
+    maxlen: Option<usize>,
+    #[arg(global = true, long, help = "read from this entry id, \"$\" for new entries only, \"0\" for the whole stream (stream mode)", default_value = "$")]
+    from_id: String,
+    #[arg(global = true, long, help = "read as a member of consumer group, created if it doesn't exist (stream mode)")]
+    group: Option<String>,
+    #[arg(global = true, long, help = "consumer name within the group [default: mqcat-<pid>] (stream mode)")]
+    consumer: Option<String>,
+    #[arg(global = true, long, help = "don't acknowledge entries read by consumer group (stream mode)")]
+    no_ack: bool,
+}
+
+// pattern subscriptions are used for channels with glob characters
+fn is_pattern(channel: &str) -> bool {
+    channel.contains(['*', '?', '['])
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::BulkString(data) => String::from_utf8_lossy(data).into_owned(),
+        Value::SimpleString(s) => s.clone(),
+        Value::Int(n) => n.to_string(),
+        value => format!("{:?}", value),
+    }
+}
+
+/// Parse `INFO` reply into (key, value) pairs, skipping section headers.
+fn parse_info(info: &str) -> Vec<(&str, &str)> {
+    info.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.trim_end().split_once(':'))
+        .collect()
+}
+
+struct RedisMQ<const STREAM: bool> {
+    url: String,
+    client: Client,
+    connection: MultiplexedConnection,
+    options: RedisOptions,
+}
+
+impl<const STREAM: bool> RedisMQ<STREAM> {
+    async fn xadd(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<String> {
+        let mut command = redis::cmd("XADD");
+        command.arg(topic);
+        if let Some(maxlen) = self.options.maxlen {
+            command.arg("MAXLEN").arg("~").arg(maxlen);
+        }
+        command.arg("*");
+        for (key, value) in headers {
+            command.arg(key).arg(value);
+        }
+        command.arg(&self.options.payload_field).arg(payload);
+        let id: String = command.query_async(&mut self.connection.clone()).await?;
+        Ok(id)
+    }
+
+    fn stream_subscribe(&self, topic: &str) -> impl Stream<Item = anyhow::Result<Frame>> {
+        let topic = topic.to_owned();
+
+        async_stream::try_stream! {
+            // blocking reads need their own connection
+            let mut connection = self.client.get_multiplexed_async_connection().await?;
+
+            let consumer = self.options.consumer.clone()
+                .unwrap_or_else(|| format!("mqcat-{}", std::process::id()));
+            let mut read_options = StreamReadOptions::default().block(0);
+            let mut last_id = self.options.from_id.clone();
+            if let Some(group) = &self.options.group {
+                let created: redis::RedisResult<()> = redis::cmd("XGROUP")
+                    .arg("CREATE").arg(&topic).arg(group).arg(&last_id).arg("MKSTREAM")
+                    .query_async(&mut connection).await;
+                match created {
+                    Ok(()) => log::info!("created consumer group \"{}\"", group),
+                    Err(err) if err.code() == Some("BUSYGROUP") => {}
+                    Err(err) => Err(anyhow!("failed to create consumer group: {}", err))?,
+                }
+                read_options = read_options.group(group, &consumer);
+                // only entries never delivered to other consumers
+                last_id = ">".to_string();
+            }
+
+            loop {
+                let reply: StreamReadReply = connection.xread_options(&[&topic], &[&last_id], &read_options).await
+                    .map_err(|err| anyhow!("read failed: {}", err))?;
+                for entry in reply.keys.into_iter().flat_map(|key| key.ids) {
+                    let mut frame = Frame {
+                        topic: topic.clone(),
+                        headers: Default::default(),
+                        payload: vec![],
+                    };
+                    frame.headers.insert("Id".to_string(), vec![entry.id.clone()]);
+                    for (field, value) in &entry.map {
+                        if *field == self.options.payload_field {
+                            frame.payload = match value {
+                                Value::BulkString(data) => data.clone(),
+                                value => value_to_string(value).into_bytes(),
+                            };
+                        } else {
+                            frame.headers.insert(field.clone(), vec![value_to_string(value)]);
+                        }
+                    }
+
+                    if let Some(group) = &self.options.group {
+                        if !self.options.no_ack {
+                            let _: i64 = connection.xack(&topic, group, &[&entry.id]).await
+                                .map_err(|err| anyhow!("ack failed: {}", err))?;
+                        }
+                    } else {
+                        last_id = entry.id;
+                    }
+                    yield frame;
+                }
+            }
+        }
+    }
+
+    fn pubsub_subscribe(&self, topic: &str) -> impl Stream<Item = anyhow::Result<Frame>> {
+        let topic = topic.to_owned();
+
+        async_stream::try_stream! {
+            let mut pubsub = self.client.get_async_pubsub().await?;
+            if is_pattern(&topic) {
+                pubsub.psubscribe(&topic).await
+                    .map_err(|err| anyhow!("subscription failed: {}", err))?;
+            } else {
+                pubsub.subscribe(&topic).await
+                    .map_err(|err| anyhow!("subscription failed: {}", err))?;
+            }
+
+            let mut messages = pubsub.on_message();
+            while let Some(message) = messages.next().await {
+                let mut frame = Frame {
+                    topic: message.get_channel_name().to_owned(),
+                    headers: Default::default(),
+                    payload: message.get_payload_bytes().to_vec(),
+                };
+                if message.from_pattern() {
+                    let pattern: String = message.get_pattern()?;
+                    frame.headers.insert("Pattern".to_string(), vec![pattern]);
+                }
+                yield frame;
+            }
+            Err(anyhow!("connection closed"))?;
+        }
+    }
+}
+
+impl<const STREAM: bool> MessageQueue for RedisMQ<STREAM> {
+    type Options = RedisOptions;
+    type Commands = NoCommands;
+    type PublishOptions = NoOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = TopicPublisher<'a, Self>;
+
+    async fn connect(addr: Option<&str>, options: &RedisOptions) -> anyhow::Result<Self> {
+        let url = addr.unwrap_or("redis://localhost:6379").to_owned();
+        let client = Client::open(url.as_str())
+            .map_err(|err| anyhow!("invalid url: {}", err))?;
+        let connection = tokio::time::timeout(Duration::from_secs(10), client.get_multiplexed_async_connection()).await
+            .map_err(|_| anyhow!("failed to connect: timed out"))?
+            .map_err(|err| anyhow!("failed to connect: {}", err))?;
+        Ok(Self { url, client, connection, options: options.clone() })
+    }
+
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
+        let text: String = redis::cmd("INFO").query_async(&mut self.connection.clone()).await
+            .map_err(|err| anyhow!("failed to get info: {}", err))?;
+        let server_info = parse_info(&text);
+        let get = |key: &str| {
+            server_info.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_string()).unwrap_or_else(|| "-".to_string())
+        };
+
+        let mut info = vec![];
+        info.push(("URL", self.url.clone()));
+        info.push(("Mode", if STREAM { "Streams" } else { "Pub/Sub" }.to_owned()));
+        info.push(("Server Version", get("redis_version")));
+        info.push(("Server Mode", get("redis_mode")));
+        info.push(("Role", get("role")));
+        if let Some(uptime) = get("uptime_in_seconds").parse().ok().map(Duration::from_secs) {
+            info.push(("Uptime", format!("{:?}", uptime)));
+        }
+        info.push(("Connected Clients", get("connected_clients")));
+        info.push(("Used Memory", get("used_memory_human")));
+        info.push(("Peak Memory", get("used_memory_peak_human")));
+        info.push(("Max Memory", format!("{} ({})", get("maxmemory_human"), get("maxmemory_policy"))));
+        info.push(("Pub/Sub Channels", get("pubsub_channels")));
+        info.push(("Pub/Sub Patterns", get("pubsub_patterns")));
+
+        Ok(format_info(&info, json))
+    }
+
+    async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        if STREAM {
+            let id = self.xadd(topic, headers, payload).await
+                .map_err(|err| anyhow!("failed to publish: {}", err))?;
+            log::debug!("added entry {}", id);
+            return Ok(());
+        }
+
+        if headers.len() > 0 {
+            log::warn!("setting headers is only supported in stream mode (redis+stream://)");
+        }
+        let receivers: i64 = self.connection.clone().publish(topic, payload).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        log::debug!("received by {} subscribers", receivers);
+        Ok(())
+    }
+
+    async fn publisher(&self, topic: &str, _options: &NoOptions) -> anyhow::Result<TopicPublisher<'_, Self>> {
+        Ok(TopicPublisher::new(self, topic))
+    }
+
+    fn subscribe(&self, topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        if STREAM {
+            self.stream_subscribe(topic).left_stream()
+        } else {
+            self.pubsub_subscribe(topic).right_stream()
+        }
+    }
+
+    async fn request(&self, _topic: &str, _headers: &[(String, String)], _payload: &[u8]) -> anyhow::Result<Frame> {
+        bail!("request is not supported by redis");
+    }
+
+    async fn command(_addr: Option<&str>, _options: &RedisOptions, command: NoCommands) -> anyhow::Result<()> {
+        match command {}
+    }
+}
+
+pub async fn run<const STREAM: bool>(args: impl Iterator<Item = String>) {
+    crate::cli::run::<RedisMQ<STREAM>>(args).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    use super::*;
+
+    /// Reads one RESP array of bulk strings, `None` when the client hung up.
+    fn read_command(reader: &mut impl BufRead) -> Option<Vec<String>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok().filter(|&n| n > 0)?;
+        let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+        let mut args = vec![];
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).ok()?;
+            let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+            let mut data = vec![0; len + 2];
+            reader.read_exact(&mut data).ok()?;
+            args.push(String::from_utf8_lossy(&data[..len]).into_owned());
+        }
+        Some(args)
+    }
+
+    /// Answers the commands mqcat sends and passes them on, the second XREADGROUP never returns.
+    fn redis_stand_in() -> (String, mpsc::Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut reads = 0;
+                    while let Some(command) = read_command(&mut reader) {
+                        let reply: &[u8] = match command[0].to_uppercase().as_str() {
+                            "XADD" => b"$3\r\n1-0\r\n",
+                            "XACK" => b":1\r\n",
+                            "XREADGROUP" if reads > 0 => b"",
+                            "XREADGROUP" => {
+                                reads += 1;
+                                b"*1\r\n*2\r\n$6\r\nevents\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*4\r\n\
+                                  $6\r\nSource\r\n$4\r\ntest\r\n$4\r\nbody\r\n$2\r\nhi\r\n"
+                            }
+                            _ => b"+OK\r\n",
+                        };
+                        let _ = sender.send(command);
+                        stream.write_all(reply).unwrap();
+                    }
+                });
+            }
+        });
+        (url, receiver)
+    }
+
+    fn options() -> RedisOptions {
+        RedisOptions {
+            payload_field: "body".to_string(),
+            maxlen: None,
+            from_id: "$".to_string(),
+            group: None,
+            consumer: None,
+            no_ack: false,
+        }
+    }
+
+    /// Commands received so far, without the client setup.
+    fn commands(receiver: &mpsc::Receiver<Vec<String>>) -> Vec<String> {
+        receiver.try_iter()
+            .filter(|command| command[0] != "CLIENT")
+            .map(|command| command.join(" "))
+            .collect()
+    }
+
+    #[test]
+    fn info_sections() {
+        let info = "# Server\r\nredis_version:7.2.4\r\nredis_mode:standalone\r\n\r\n# Replication\r\nrole:master\r\n";
+        assert_eq!(parse_info(info), vec![
+            ("redis_version", "7.2.4"),
+            ("redis_mode", "standalone"),
+            ("role", "master"),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn stream_publish() {
+        let (url, receiver) = redis_stand_in();
+        let options = RedisOptions { maxlen: Some(1000), ..options() };
+        let mq = RedisMQ::<true>::connect(Some(&url), &options).await.unwrap();
+        mq.publish("events", &[("Source".to_string(), "test".to_string())], b"hi").await.unwrap();
+        assert_eq!(commands(&receiver), ["XADD events MAXLEN ~ 1000 * Source test body hi"]);
+    }
+
+    #[tokio::test]
+    async fn stream_group_read() {
+        let (url, receiver) = redis_stand_in();
+        let options = RedisOptions {
+            from_id: "0".to_string(),
+            group: Some("workers".to_string()),
+            consumer: Some("w1".to_string()),
+            no_ack: true,
+            ..options()
+        };
+        let mq = RedisMQ::<true>::connect(Some(&url), &options).await.unwrap();
+        let frame = Box::pin(mq.subscribe("events", &NoOptions {})).next().await.unwrap().unwrap();
+        assert_eq!(frame.topic, "events");
+        assert_eq!(frame.payload, b"hi");
+        assert_eq!(frame.headers["Id"], ["1-0"]);
+        assert_eq!(frame.headers["Source"], ["test"]);
+
+        // entries stay pending with --no-ack
+        assert_eq!(commands(&receiver), [
+            "XGROUP CREATE events workers 0 MKSTREAM",
+            "XREADGROUP GROUP workers w1 BLOCK 0 STREAMS events >",
+        ]);
+    }
+}
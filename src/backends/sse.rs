@@ -0,0 +1,258 @@
+use std::io::BufRead;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use clap::Args;
+use futures_util::Stream;
+use ureq::Agent;
+
+use crate::backends::http::response_to_frame;
+use crate::cli::{NoCommands, NoOptions, parse_header};
+use crate::mqtrait::{Frame, MessageQueue, TopicPublisher};
+use crate::utils::{format_info, topic_url};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "SSE Options")]
+pub struct SseOptions {
+    #[arg(global = true, long, help = "add request header, e.g. --request-header 'Authorization: Bearer abc'", value_parser = parse_header)]
+    request_header: Vec<(String, String)>,
+    #[arg(global = true, long, help = "resume after this event id (sent as Last-Event-ID)")]
+    last_event_id: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Event {
+    name: Option<String>,
+    id: Option<String>,
+    retry: Option<u64>,
+    // none when only the last event id changed
+    data: Option<String>,
+}
+
+/// Line-by-line parser of `text/event-stream` (https://html.spec.whatwg.org/multipage/server-sent-events.html).
+#[derive(Default)]
+struct Parser {
+    name: Option<String>,
+    data: Option<String>,
+    // last event id and reconnection time carry over to the following events
+    last_event_id: Option<String>,
+    retry: Option<u64>,
+    id_changed: bool,
+}
+
+impl Parser {
+    /// Returns an event when a blank line completes it, or an event without data if only its id was set.
+    fn line(&mut self, line: &str) -> Option<Event> {
+        if line.is_empty() {
+            let name = self.name.take();
+            let data = self.data.take();
+            if data.is_none() && !std::mem::take(&mut self.id_changed) {
+                return None;
+            }
+            self.id_changed = false;
+            let data = data.map(|mut data| {
+                if data.ends_with('\n') {
+                    data.pop();
+                }
+                data
+            });
+            return Some(Event { name, id: self.last_event_id.clone(), retry: self.retry, data });
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => self.name = Some(value.to_owned()),
+            "data" => {
+                let data = self.data.get_or_insert_default();
+                data.push_str(value);
+                data.push('\n');
+            }
+            "id" if !value.contains('\0') => {
+                // empty id resets the last event id
+                self.last_event_id = (!value.is_empty()).then(|| value.to_owned());
+                self.id_changed = true;
+            }
+            "retry" => self.retry = value.parse().ok().or(self.retry),
+            _ => log::debug!("ignoring field \"{}\"", field),
+        }
+        None
+    }
+}
+
+fn event_to_frame(event: Event) -> Frame {
+    let mut frame = Frame {
+        topic: event.name.unwrap_or_else(|| "message".to_string()),
+        headers: Default::default(),
+        payload: event.data.unwrap_or_default().into_bytes(),
+    };
+    if let Some(id) = event.id {
+        frame.headers.insert("Id".to_string(), vec![id]);
+    }
+    if let Some(retry) = event.retry {
+        frame.headers.insert("Retry".to_string(), vec![retry.to_string()]);
+    }
+    frame
+}
+
+struct SseMQ {
+    url: String,
+    agent: Agent,
+    options: SseOptions,
+    // sent on resubscribe, so reconnects continue where the stream stopped
+    last_event_id: Mutex<Option<String>>,
+}
+
+impl SseMQ {
+    /// Opens the stream on a blocking thread, ureq is blocking.
+    async fn open(&self, url: &str) -> anyhow::Result<ureq::http::Response<ureq::Body>> {
+        let mut request = self.agent.get(url)
+            .header("Accept", "text/event-stream")
+            .header("Cache-Control", "no-cache");
+        for (key, value) in &self.options.request_header {
+            request = request.header(key, value);
+        }
+        if let Some(id) = &*self.last_event_id.lock().unwrap() {
+            request = request.header("Last-Event-ID", id);
+        }
+        Ok(tokio::task::spawn_blocking(move || request.call()).await??)
+    }
+}
+
+impl MessageQueue for SseMQ {
+    type Options = SseOptions;
+    type Commands = NoCommands;
+    type PublishOptions = NoOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = TopicPublisher<'a, Self>;
+
+    async fn connect(addr: Option<&str>, options: &SseOptions) -> anyhow::Result<Self> {
+        let url = addr.unwrap_or("http://localhost:8080").to_owned();
+        let agent = Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_connect(Some(CONNECT_TIMEOUT))
+            .timeout_recv_response(Some(CONNECT_TIMEOUT))
+            .build()
+            .into();
+        let last_event_id = Mutex::new(options.last_event_id.clone());
+        Ok(Self { url, agent, options: options.clone(), last_event_id })
+    }
+
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
+        // only the response headers are needed, the stream is closed right away
+        let response = self.open(&self.url).await
+            .map_err(|err| anyhow!("request failed: {}", err))?;
+        let frame = response_to_frame("", &response, vec![]);
+
+        let mut info = vec![("URL", self.url.clone())];
+        for (key, values) in &frame.headers {
+            for value in values {
+                info.push((key.as_str(), value.clone()));
+            }
+        }
+        Ok(format_info(&info, json))
+    }
+
+    async fn publish(&self, _topic: &str, _headers: &[(String, String)], _payload: &[u8]) -> anyhow::Result<()> {
+        bail!("publish is not supported by sse, use http:// to post messages");
+    }
+
+    async fn publisher(&self, topic: &str, _options: &NoOptions) -> anyhow::Result<TopicPublisher<'_, Self>> {
+        Ok(TopicPublisher::new(self, topic))
+    }
+
+    fn subscribe(&self, topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        let url = topic_url(&self.url, topic);
+
+        async_stream::try_stream! {
+            let response = self.open(&url).await
+                .map_err(|err| anyhow!("failed to connect: {}", err))?;
+            let status = response.status();
+            if !status.is_success() {
+                Err(anyhow!("failed to connect: http status {}", status))?;
+            }
+            let content_type = response.headers().get("content-type")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            if !content_type.starts_with("text/event-stream") {
+                log::warn!("unexpected content type \"{}\"", content_type);
+            }
+
+            // the body is read on a blocking thread, events are passed back over a channel
+            let (sender, mut receiver) = tokio::sync::mpsc::channel(64);
+            let reader = std::io::BufReader::new(response.into_body().into_reader());
+            tokio::task::spawn_blocking(move || {
+                let mut parser = Parser::default();
+                for line in reader.lines() {
+                    let event = match line {
+                        Ok(line) => match parser.line(&line) {
+                            Some(event) => Ok(event),
+                            None => continue,
+                        },
+                        Err(err) => Err(anyhow!("failed to receive: {}", err)),
+                    };
+                    let failed = event.is_err();
+                    if sender.blocking_send(event).is_err() || failed {
+                        return;
+                    }
+                }
+                let _ = sender.blocking_send(Err(anyhow!("stream ended")));
+            });
+
+            while let Some(event) = receiver.recv().await {
+                let event = event?;
+                self.last_event_id.lock().unwrap().clone_from(&event.id);
+                if event.data.is_some() {
+                    yield event_to_frame(event);
+                }
+            }
+        }
+    }
+
+    async fn request(&self, _topic: &str, _headers: &[(String, String)], _payload: &[u8]) -> anyhow::Result<Frame> {
+        bail!("request is not supported by sse, use http:// to post requests");
+    }
+
+    async fn command(_addr: Option<&str>, _options: &SseOptions, command: NoCommands) -> anyhow::Result<()> {
+        match command {}
+    }
+}
+
+pub async fn run(args: impl Iterator<Item = String>) {
+    crate::cli::run::<SseMQ>(args).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_events() {
+        let mut parser = Parser::default();
+        let stream = ": comment\nevent: price\nid: 7\nretry: 3000\ndata: {\"a\":\ndata:1}\n\ndata\n\nid: 8\nretry: 500\n\ndata: x\n\nid\n\n\n";
+        let events: Vec<_> = stream.lines().filter_map(|line| parser.line(line)).collect();
+        let event = |id: Option<&str>, retry, data: Option<&str>| Event {
+            name: None,
+            id: id.map(str::to_owned),
+            retry: Some(retry),
+            data: data.map(str::to_owned),
+        };
+        assert_eq!(events, [
+            Event { name: Some("price".to_string()), ..event(Some("7"), 3000, Some("{\"a\":\n1}")) },
+            // id and retry persist, id without data is still passed on for Last-Event-ID
+            event(Some("7"), 3000, Some("")),
+            event(Some("8"), 500, None),
+            event(Some("8"), 500, Some("x")),
+            event(None, 500, None),
+        ]);
+
+        let frame = event_to_frame(Event { id: Some("9".to_string()), data: Some("hi".to_string()), ..Default::default() });
+        assert_eq!(frame.topic, "message");
+        assert_eq!(frame.headers["Id"], ["9"]);
+    }
+}
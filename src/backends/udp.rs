@@ -0,0 +1,378 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, anyhow, bail};
+use clap::Args;
+use futures_util::Stream;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+#[cfg(unix)]
+use tokio::net::UnixDatagram;
+
+use crate::cli::{NoCommands, NoOptions, parse_duration};
+use crate::mqtrait::{Frame, MessageQueue, Publisher};
+use crate::utils::format_info;
+
+// largest possible UDP payload
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Datagram Options")]
+pub struct UdpOptions {
+    #[arg(global = true, long, help = "interface for multicast groups: IPv4 address, or interface index for IPv6 groups [default: any]")]
+    multicast_interface: Option<String>,
+    #[arg(global = true, long, help = "TTL (hop limit) of published multicast datagrams", default_value = "1")]
+    ttl: u32,
+    #[arg(global = true, long, help = "req reply timeout", default_value = "10s", value_parser = parse_duration)]
+    timeout: Duration,
+}
+
+impl UdpOptions {
+    fn interface_v4(&self) -> anyhow::Result<Ipv4Addr> {
+        let Some(interface) = &self.multicast_interface else {
+            return Ok(Ipv4Addr::UNSPECIFIED);
+        };
+        interface.parse().map_err(|_| anyhow!("multicast interface of IPv4 group must be an IPv4 address, got \"{}\"", interface))
+    }
+
+    fn interface_v6(&self) -> anyhow::Result<u32> {
+        let Some(interface) = &self.multicast_interface else {
+            return Ok(0);
+        };
+        interface.parse().map_err(|_| anyhow!("multicast interface of IPv6 group must be an interface index, got \"{}\"", interface))
+    }
+}
+
+/// Removes a socket file bound by us once the socket is closed.
+struct SocketPath(PathBuf);
+
+impl Drop for SocketPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+enum DatagramSocket {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    // path is kept to remove the socket file on close
+    Unix { socket: UnixDatagram, _path: Option<SocketPath> },
+}
+
+impl DatagramSocket {
+    async fn send(&self, payload: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Udp(socket) => socket.send(payload).await,
+            #[cfg(unix)]
+            Self::Unix { socket, .. } => socket.send(payload).await,
+        }
+    }
+
+    /// Receives one datagram, returning sender address when it has one.
+    async fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, Option<String>)> {
+        match self {
+            Self::Udp(socket) => {
+                let (len, addr) = socket.recv_from(buf).await?;
+                Ok((len, Some(addr.to_string())))
+            }
+            #[cfg(unix)]
+            Self::Unix { socket, .. } => {
+                let (len, addr) = socket.recv_from(buf).await?;
+                // clients that send without binding have no address
+                Ok((len, addr.as_pathname().map(|path| path.display().to_string())))
+            }
+        }
+    }
+}
+
+fn datagram_to_frame(topic: &str, sender: Option<String>, payload: &[u8]) -> Frame {
+    let mut frame = Frame {
+        topic: topic.to_owned(),
+        headers: Default::default(),
+        payload: payload.to_vec(),
+    };
+    if let Some(sender) = sender {
+        frame.headers.insert("Sender".to_string(), vec![sender]);
+    }
+    frame
+}
+
+fn into_tokio(socket: Socket) -> anyhow::Result<UdpSocket> {
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Binds a socket file, taking over a stale one left by a process that didn't clean up.
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> anyhow::Result<(UnixDatagram, SocketPath)> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let is_socket = std::fs::metadata(path).map(|meta| meta.file_type().is_socket()).unwrap_or(false);
+    if is_socket {
+        // only a refused connection means nobody is bound, any other error leaves the file alone
+        match UnixDatagram::unbound()?.connect(path) {
+            Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+                log::debug!("removing stale socket {}", path.display());
+                std::fs::remove_file(path)?;
+            }
+            Err(err) => bail!("failed to bind {}: socket file in use ({})", path.display(), err),
+            Ok(()) => bail!("failed to bind {}: socket is in use", path.display()),
+        }
+    }
+    let socket = UnixDatagram::bind(path)
+        .with_context(|| format!("failed to bind {}", path.display()))?;
+    Ok((socket, SocketPath(path.to_owned())))
+}
+
+struct DatagramMQ<const UNIX: bool> {
+    url: String,
+    // host:port for udp, socket path for unixgram
+    address: String,
+    options: UdpOptions,
+}
+
+impl<const UNIX: bool> DatagramMQ<UNIX> {
+    async fn resolve(&self) -> anyhow::Result<SocketAddr> {
+        // udp://:9000 listens on all interfaces
+        let address = match self.address.starts_with(':') {
+            true => format!("0.0.0.0{}", self.address),
+            false => self.address.clone(),
+        };
+        tokio::net::lookup_host(&address).await
+            .with_context(|| format!("failed to resolve {}", address))?
+            .next()
+            .ok_or_else(|| anyhow!("failed to resolve {}", address))
+    }
+
+    /// Socket connected to the url address, for pub and req.
+    async fn open_sender(&self, reply: bool) -> anyhow::Result<DatagramSocket> {
+        if UNIX {
+            #[cfg(unix)]
+            {
+                // replies need an address to be sent to
+                let (socket, path) = match reply {
+                    true => {
+                        let path = std::env::temp_dir().join(format!("mqcat-{}.sock", std::process::id()));
+                        let (socket, path) = bind_unix(&path)?;
+                        (socket, Some(path))
+                    }
+                    false => (UnixDatagram::unbound()?, None),
+                };
+                socket.connect(&self.address)
+                    .with_context(|| format!("failed to connect to {}", self.address))?;
+                return Ok(DatagramSocket::Unix { socket, _path: path });
+            }
+            #[cfg(not(unix))]
+            bail!("unixgram is only supported on unix");
+        }
+
+        let addr = self.resolve().await?;
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_broadcast(true)?;
+        match addr.ip() {
+            IpAddr::V4(ip) if ip.is_multicast() => {
+                socket.set_multicast_ttl_v4(self.options.ttl)?;
+                socket.set_multicast_if_v4(&self.options.interface_v4()?)?;
+            }
+            IpAddr::V6(ip) if ip.is_multicast() => {
+                socket.set_multicast_hops_v6(self.options.ttl)?;
+                socket.set_multicast_if_v6(self.options.interface_v6()?)?;
+            }
+            _ => {}
+        }
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        socket.bind(&local.into())?;
+        let socket = into_tokio(socket)?;
+        socket.connect(addr).await
+            .with_context(|| format!("failed to connect to {}", addr))?;
+        Ok(DatagramSocket::Udp(socket))
+    }
+
+    /// Socket bound to the url address, joining the group for multicast addresses.
+    async fn open_receiver(&self) -> anyhow::Result<DatagramSocket> {
+        if UNIX {
+            #[cfg(unix)]
+            {
+                let (socket, path) = bind_unix(Path::new(&self.address))?;
+                return Ok(DatagramSocket::Unix { socket, _path: Some(path) });
+            }
+            #[cfg(not(unix))]
+            bail!("unixgram is only supported on unix");
+        }
+
+        let addr = self.resolve().await?;
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        // several listeners of the same group share the port
+        socket.set_reuse_address(true)?;
+        let bind: SocketAddr = match addr.ip() {
+            IpAddr::V4(ip) if ip.is_multicast() => {
+                socket.join_multicast_v4(&ip, &self.options.interface_v4()?)
+                    .with_context(|| format!("failed to join multicast group {}", ip))?;
+                (Ipv4Addr::UNSPECIFIED, addr.port()).into()
+            }
+            IpAddr::V6(ip) if ip.is_multicast() => {
+                socket.join_multicast_v6(&ip, self.options.interface_v6()?)
+                    .with_context(|| format!("failed to join multicast group {}", ip))?;
+                (Ipv6Addr::UNSPECIFIED, addr.port()).into()
+            }
+            _ => addr,
+        };
+        socket.bind(&bind.into())
+            .with_context(|| format!("failed to bind {}", bind))?;
+        Ok(DatagramSocket::Udp(into_tokio(socket)?))
+    }
+}
+
+pub struct DatagramPublisher {
+    socket: DatagramSocket,
+}
+
+impl Publisher for DatagramPublisher {
+    async fn publish(&self, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        if headers.len() > 0 {
+            log::warn!("setting headers is not supported by datagram transports");
+        }
+        self.socket.send(payload).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        Ok(())
+    }
+}
+
+impl<const UNIX: bool> MessageQueue for DatagramMQ<UNIX> {
+    type Options = UdpOptions;
+    type Commands = NoCommands;
+    type PublishOptions = NoOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = DatagramPublisher;
+
+    async fn connect(addr: Option<&str>, options: &UdpOptions) -> anyhow::Result<Self> {
+        let (scheme, default) = match UNIX {
+            true => ("unixgram://", "unixgram:///tmp/mqcat.sock"),
+            false => ("udp://", "udp://localhost:5000"),
+        };
+        let url = addr.unwrap_or(default).to_owned();
+        let address = url.strip_prefix(scheme)
+            .ok_or_else(|| anyhow!("url must start with {}", scheme))?;
+        let address = match UNIX {
+            true => address.to_owned(),
+            false => address.trim_end_matches('/').to_owned(),
+        };
+        if address.is_empty() {
+            bail!("url has no address");
+        }
+        Ok(Self { url, address, options: options.clone() })
+    }
+
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
+        // connectionless, only show where datagrams go
+        let mut info = vec![("URL", self.url.clone())];
+        if UNIX {
+            let exists = Path::new(&self.address).exists();
+            info.push(("Path", format!("{} ({})", self.address, if exists { "exists" } else { "not found" })));
+        } else {
+            let addr = self.resolve().await?;
+            info.push(("Address", addr.to_string()));
+            if addr.ip().is_multicast() {
+                let interface = self.options.multicast_interface.as_deref().unwrap_or("any");
+                info.push(("Multicast", format!("interface {}, ttl {}", interface, self.options.ttl)));
+            }
+        }
+        Ok(format_info(&info, json))
+    }
+
+    async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        self.publisher(topic, &NoOptions {}).await?.publish(headers, payload).await
+    }
+
+    async fn publisher(&self, _topic: &str, _options: &NoOptions) -> anyhow::Result<DatagramPublisher> {
+        let socket = self.open_sender(false).await?;
+        Ok(DatagramPublisher { socket })
+    }
+
+    fn subscribe(&self, topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        // datagrams have no channel, the given one only labels received frames
+        let topic = topic.to_owned();
+
+        async_stream::try_stream! {
+            let socket = self.open_receiver().await?;
+            let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+            loop {
+                let (len, sender) = socket.recv_from(&mut buf).await
+                    .map_err(|err| anyhow!("failed to receive: {}", err))?;
+                yield datagram_to_frame(&topic, sender, &buf[..len]);
+            }
+        }
+    }
+
+    async fn request(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<Frame> {
+        if headers.len() > 0 {
+            log::warn!("setting headers is not supported by datagram transports");
+        }
+        let socket = self.open_sender(true).await?;
+        socket.send(payload).await
+            .map_err(|err| anyhow!("failed to send request: {}", err))?;
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        let (len, sender) = tokio::time::timeout(self.options.timeout, socket.recv_from(&mut buf)).await
+            .map_err(|_| anyhow!("no reply within {:?}", self.options.timeout))?
+            .map_err(|err| anyhow!("failed to receive reply: {}", err))?;
+        Ok(datagram_to_frame(topic, sender, &buf[..len]))
+    }
+
+    async fn command(_addr: Option<&str>, _options: &UdpOptions, command: NoCommands) -> anyhow::Result<()> {
+        match command {}
+    }
+}
+
+pub async fn run<const UNIX: bool>(args: impl Iterator<Item = String>) {
+    crate::cli::run::<DatagramMQ<UNIX>>(args).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+
+    use super::*;
+
+    fn options() -> UdpOptions {
+        UdpOptions { multicast_interface: None, ttl: 1, timeout: Duration::from_secs(5) }
+    }
+
+    async fn receive_one<const UNIX: bool>(url: &str, publish: &str) -> Frame {
+        let subscriber = DatagramMQ::<UNIX>::connect(Some(url), &options()).await.unwrap();
+        let mut stream = Box::pin(subscriber.subscribe("telemetry", &NoOptions {}));
+        let received = async { stream.next().await.unwrap().unwrap() };
+        let publisher = DatagramMQ::<UNIX>::connect(Some(publish), &options()).await.unwrap();
+        let send = async {
+            // the subscriber binds on first poll
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            publisher.publish("telemetry", &[], b"{\"speed\": 1.5}").await.unwrap();
+        };
+        let (frame, _) = tokio::time::timeout(Duration::from_secs(5), async { tokio::join!(received, send) }).await.unwrap();
+        frame
+    }
+
+    #[tokio::test]
+    async fn udp_datagrams() {
+        let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let frame = receive_one::<false>(&format!("udp://127.0.0.1:{}", port), &format!("udp://127.0.0.1:{}/", port)).await;
+        assert_eq!(frame.topic, "telemetry");
+        assert_eq!(frame.payload, b"{\"speed\": 1.5}");
+        assert!(frame.headers["Sender"][0].starts_with("127.0.0.1:"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_datagrams() {
+        let path = std::env::temp_dir().join(format!("mqcat-unixgram-test-{}.sock", std::process::id()));
+        let url = format!("unixgram://{}", path.display());
+        let frame = receive_one::<true>(&url, &url).await;
+        assert_eq!(frame.payload, b"{\"speed\": 1.5}");
+        // unbound sender has no address
+        assert!(frame.headers.is_empty());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use clap::Args;
+use futures_util::{SinkExt, Stream, StreamExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::cli::{NoCommands, NoOptions, parse_header};
+use crate::mqtrait::{Frame, MessageQueue, Publisher};
+use crate::utils::{format_info, topic_url, url_path};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "WebSocket Options")]
+pub struct WsOptions {
+    #[arg(global = true, long, help = "add handshake header, for all commands (pub/req also use -H)", value_parser = parse_header)]
+    handshake_header: Vec<(String, String)>,
+    #[arg(global = true, long, help = "send binary frames (text frames are used for valid UTF-8 payloads by default)")]
+    binary: bool,
+}
+
+fn message_to_frame(topic: &str, message: Message) -> Option<Frame> {
+    let (kind, payload) = match message {
+        Message::Text(text) => ("text", text.as_bytes().to_vec()),
+        Message::Binary(data) => ("binary", data.to_vec()),
+        _ => return None,
+    };
+    let mut frame = Frame {
+        topic: topic.to_owned(),
+        headers: Default::default(),
+        payload,
+    };
+    frame.headers.insert("Type".to_string(), vec![kind.to_string()]);
+    Some(frame)
+}
+
+struct WsMQ {
+    url: String,
+    options: WsOptions,
+}
+
+impl WsMQ {
+    async fn open(&self, url: &str, headers: &[(String, String)]) -> anyhow::Result<(Socket, Vec<(String, String)>)> {
+        let mut request = url.into_client_request()
+            .map_err(|err| anyhow!("invalid url: {}", err))?;
+        for (key, value) in self.options.handshake_header.iter().chain(headers) {
+            request.headers_mut().append(HeaderName::from_bytes(key.as_bytes())?, HeaderValue::from_str(value)?);
+        }
+        let (socket, response) = tokio::time::timeout(TIMEOUT, tokio_tungstenite::connect_async(request)).await
+            .map_err(|_| anyhow!("failed to connect: timed out"))?
+            .map_err(|err| anyhow!("failed to connect: {}", err))?;
+        let response_headers = response.headers().iter()
+            .map(|(key, value)| (key.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+            .collect();
+        Ok((socket, response_headers))
+    }
+
+    fn message(&self, payload: &[u8]) -> Message {
+        match std::str::from_utf8(payload) {
+            Ok(text) if !self.options.binary => Message::text(text),
+            _ => Message::binary(payload.to_vec()),
+        }
+    }
+
+    async fn receive(socket: &mut Socket, topic: &str) -> anyhow::Result<Frame> {
+        while let Some(message) = socket.next().await {
+            let message = message.map_err(|err| anyhow!("failed to receive: {}", err))?;
+            if let Message::Close(close) = &message {
+                match close {
+                    Some(close) => bail!("connection closed: {} (code={})", close.reason, u16::from(close.code)),
+                    None => bail!("connection closed"),
+                }
+            }
+            if let Some(frame) = message_to_frame(topic, message) {
+                return Ok(frame);
+            }
+        }
+        bail!("connection closed")
+    }
+}
+
+/// Keeps the connection open between messages, it's opened on first publish
+/// since handshake headers come with the message.
+pub struct WsPublisher<'a> {
+    mq: &'a WsMQ,
+    url: String,
+    socket: Mutex<Option<Socket>>,
+}
+
+impl Publisher for WsPublisher<'_> {
+    async fn publish(&self, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        let mut socket = self.socket.lock().await;
+        if socket.is_none() {
+            *socket = Some(self.mq.open(&self.url, headers).await?.0);
+        }
+        socket.as_mut().unwrap().send(self.mq.message(payload)).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        Ok(())
+    }
+}
+
+impl MessageQueue for WsMQ {
+    type Options = WsOptions;
+    type Commands = NoCommands;
+    type PublishOptions = NoOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = WsPublisher<'a>;
+
+    async fn connect(addr: Option<&str>, options: &WsOptions) -> anyhow::Result<Self> {
+        let url = addr.unwrap_or("ws://localhost:8080").to_owned();
+        Ok(Self { url, options: options.clone() })
+    }
+
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
+        let (mut socket, response_headers) = self.open(&self.url, &[]).await?;
+        let _ = socket.close(None).await;
+
+        let mut info = vec![("URL", self.url.clone())];
+        for (key, value) in &response_headers {
+            // handshake details are implied by a successful connection
+            if ["connection", "upgrade", "sec-websocket-accept"].contains(&key.as_str()) {
+                continue;
+            }
+            info.push((key.as_str(), value.clone()));
+        }
+        Ok(format_info(&info, json))
+    }
+
+    async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        let (mut socket, _) = self.open(&topic_url(&self.url, topic), headers).await?;
+        socket.send(self.message(payload)).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        let _ = socket.close(None).await;
+        Ok(())
+    }
+
+    async fn publisher(&self, topic: &str, _options: &NoOptions) -> anyhow::Result<WsPublisher<'_>> {
+        Ok(WsPublisher { mq: self, url: topic_url(&self.url, topic), socket: Mutex::new(None) })
+    }
+
+    fn subscribe(&self, topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        let url = topic_url(&self.url, topic);
+
+        async_stream::try_stream! {
+            let (mut socket, _) = self.open(&url, &[]).await?;
+            let topic = url_path(&url);
+            loop {
+                yield Self::receive(&mut socket, topic).await?;
+            }
+        }
+    }
+
+    async fn request(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<Frame> {
+        // reply is the first data frame received after sending the request
+        let url = topic_url(&self.url, topic);
+        let (mut socket, _) = self.open(&url, headers).await?;
+        socket.send(self.message(payload)).await
+            .map_err(|err| anyhow!("failed to send request: {}", err))?;
+        let reply = Self::receive(&mut socket, url_path(&url)).await?;
+        let _ = socket.close(None).await;
+        Ok(reply)
+    }
+
+    async fn command(_addr: Option<&str>, _options: &WsOptions, command: NoCommands) -> anyhow::Result<()> {
+        match command {}
+    }
+}
+
+pub async fn run(args: impl Iterator<Item = String>) {
+    crate::cli::run::<WsMQ>(args).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[allow(clippy::result_large_err)]
+    async fn echo_request() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut token = None;
+            let callback = |request: &tokio_tungstenite::tungstenite::handshake::server::Request, response| {
+                token = request.headers().get("authorization").map(|v| v.to_str().unwrap().to_owned());
+                Ok(response)
+            };
+            let mut socket = tokio_tungstenite::accept_hdr_async(stream, callback).await.unwrap();
+            let message = socket.next().await.unwrap().unwrap();
+            socket.send(message).await.unwrap();
+            token
+        });
+
+        let options = WsOptions { handshake_header: vec![], binary: false };
+        let mq = WsMQ::connect(Some(&url), &options).await.unwrap();
+        let headers = [("Authorization".to_string(), "Bearer abc".to_string())];
+        let reply = mq.request("echo", &headers, b"hello").await.unwrap();
+        assert_eq!(reply.topic, "/echo");
+        assert_eq!(reply.payload, b"hello");
+        assert_eq!(reply.headers["Type"], ["text"]);
+        assert_eq!(server.await.unwrap().as_deref(), Some("Bearer abc"));
+    }
+}
@@ -1,19 +1,507 @@
+use std::io::Write;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 
 use anyhow::{anyhow, bail};
+use clap::{Args, Subcommand, ValueEnum};
 use futures_util::Stream;
-use zenoh::Session;
+use zenoh::{Config, Session};
 use zenoh::bytes::Encoding;
-use zenoh::query::QueryTarget;
+use zenoh::config::{WhatAmI, WhatAmIMatcher};
+use zenoh::qos::{CongestionControl, Priority, Reliability};
+use zenoh::key_expr::KeyExpr;
+use zenoh::query::{ConsolidationMode, Query, QueryTarget, Reply};
+use zenoh::sample::Sample;
 
-use crate::mqtrait::{Frame, MessageQueue};
-use crate::utils::format_table;
+use crate::cli::{NoOptions, data_or_stdin, parse_duration, parse_header, print_data};
+use crate::mqtrait::{ConnectionEvent, Frame, MessageQueue, Publisher};
+use crate::utils::{format_info, format_table};
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Zenoh Options")]
+pub struct ZenohOptions {
+    #[arg(global = true, long, help = "load zenoh configuration from a json5 file")]
+    zenoh_config: Option<PathBuf>,
+    #[arg(global = true, long, help = "session mode", value_enum)]
+    mode: Option<Mode>,
+    #[arg(global = true, long, help = "endpoint to listen on, e.g. tcp/0.0.0.0:7447")]
+    listen: Vec<String>,
+    #[arg(global = true, long, help = "disable multicast scouting")]
+    no_scouting: bool,
+    #[arg(global = true, long, help = "override configuration value, e.g. --cfg 'transport/shared_memory/enabled=false'", value_parser = parse_cfg)]
+    cfg: Vec<(String, String)>,
+}
+
+/// QoS of pub and del.
+#[derive(Args, Debug, Clone)]
+pub struct QosOptions {
+    #[arg(long, help = "priority, from 1 (real-time) to 7 (background), default 5", value_parser = clap::value_parser!(u8).range(1..=7))]
+    priority: Option<u8>,
+    #[arg(long, help = "send without batching (lower latency)")]
+    express: bool,
+    #[arg(long, help = "congestion control", value_enum)]
+    congestion: Option<Congestion>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Zenoh Publish Options")]
+pub struct ZenohPublishOptions {
+    #[command(flatten)]
+    qos: QosOptions,
+    #[arg(long, help = "reliability", value_enum)]
+    reliability: Option<ReliabilityMode>,
+    #[arg(long, help = "wait for matching subscribers up to this duration (0s to disable)", default_value = "5s", value_parser = parse_duration)]
+    wait_for_subscribers: Duration,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Mode {
+    Peer,
+    Client,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Congestion {
+    Block,
+    Drop,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ReliabilityMode {
+    Reliable,
+    BestEffort,
+}
+
+fn parse_cfg(s: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = s.split_once('=') else {
+        return Err("config override must be in the format of \"key=value\"".to_string());
+    };
+    Ok((key.trim().to_string(), value.trim().to_string()))
+}
+
+impl ZenohOptions {
+    fn config(&self, addr: Option<&str>) -> anyhow::Result<Config> {
+        let mut config = if let Some(path) = &self.zenoh_config {
+            Config::from_file(path).map_err(|err| anyhow!("failed to load {:?}: {}", path, err))?
+        } else {
+            Config::default()
+        };
+
+        if let Some(mode) = self.mode {
+            let mode = match mode {
+                Mode::Peer => "peer",
+                Mode::Client => "client",
+            };
+            config.insert_json5("mode", &serde_json::json!(mode).to_string()).unwrap();
+        }
+        if let Some(addr) = addr {
+            config
+                .insert_json5("connect/endpoints", &serde_json::json!([addr]).to_string())
+                .unwrap();
+        }
+        if !self.listen.is_empty() {
+            config
+                .insert_json5("listen/endpoints", &serde_json::json!(self.listen).to_string())
+                .map_err(|err| anyhow!("invalid listen endpoints: {}", err))?;
+        }
+        if self.no_scouting {
+            config.insert_json5("scouting/multicast/enabled", "false").unwrap();
+        }
+        // applied last, so they take precedence over everything else
+        for (key, value) in &self.cfg {
+            config
+                .insert_json5(key, value)
+                .map_err(|err| anyhow!("invalid config override {}={}: {}", key, value, err))?;
+        }
+
+        Ok(config)
+    }
+}
+
+impl QosOptions {
+    fn priority(&self) -> Priority {
+        self.priority
+            .and_then(|p| Priority::try_from(p).ok())
+            .unwrap_or_default()
+    }
+
+    fn congestion_control(&self) -> CongestionControl {
+        match self.congestion {
+            Some(Congestion::Block) => CongestionControl::Block,
+            Some(Congestion::Drop) => CongestionControl::Drop,
+            None => CongestionControl::default(),
+        }
+    }
+
+}
+
+impl ZenohPublishOptions {
+    fn reliability(&self) -> Reliability {
+        match self.reliability {
+            Some(ReliabilityMode::Reliable) => Reliability::Reliable,
+            Some(ReliabilityMode::BestEffort) => Reliability::BestEffort,
+            None => Reliability::default(),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ZenohCommands {
+    #[command(about = "delete a key expression (e.g. from storages)", alias = "del")]
+    Delete {
+        #[arg(help = "key expression")]
+        keyexpr: String,
+        #[command(flatten)]
+        qos: QosOptions,
+    },
+
+    #[command(about = "query a selector and print all replies (storages, queryables)")]
+    Get {
+        #[arg(help = "selector, e.g. \"demo/**?x=1;y=2\"")]
+        selector: String,
+        #[arg(help = "query payload (empty if not provided)")]
+        data: Option<String>,
+        #[arg(short = 'H', long, help = "add header to the query", value_parser = parse_header)]
+        header: Vec<(String, String)>,
+        #[arg(long, help = "queryables to send the query to", value_enum, default_value = "best-matching")]
+        target: Target,
+        #[arg(long, help = "consolidation mode for replies", value_enum, default_value = "auto")]
+        consolidation: Consolidation,
+        #[arg(long, help = "query timeout", default_value = "10s", value_parser = parse_duration)]
+        timeout: Duration,
+        #[arg(long, help = "decode the message by passing it through a given command")]
+        translate: Option<String>,
+    },
+
+    #[command(about = "declare a queryable and reply to incoming queries")]
+    Reply {
+        #[arg(help = "key expression")]
+        keyexpr: String,
+        #[arg(help = "reply data (read from stdin if not provided)")]
+        data: Option<String>,
+        #[arg(short = 'H', long, help = "add header to the reply", value_parser = parse_header)]
+        header: Vec<(String, String)>,
+        #[arg(long, help = "declare queryable as complete (answers all-complete queries)")]
+        complete: bool,
+        #[arg(long, help = "decode the message by passing it through a given command")]
+        translate: Option<String>,
+    },
+
+    #[command(about = "observe or declare liveliness tokens", subcommand)]
+    Liveliness(LivelinessCommands),
+
+    #[command(about = "discover routers and peers using scouting (no session required)")]
+    Scout {
+        #[arg(long, help = "how long to wait for replies", default_value = "3s", value_parser = parse_duration)]
+        duration: Duration,
+        #[arg(long, help = "kind of nodes to look for", value_enum, default_values = ["router", "peer"])]
+        what: Vec<What>,
+    },
+
+    #[command(about = "browse admin space (router info, linkstate, subscribers, queryables, plugins)")]
+    Admin {
+        #[arg(help = "admin space selector", default_value = "@/**")]
+        selector: String,
+        #[arg(long, help = "print as json instead of a tree")]
+        json: bool,
+        #[arg(long, help = "query timeout", default_value = "10s", value_parser = parse_duration)]
+        timeout: Duration,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum What {
+    Router,
+    Peer,
+    Client,
+}
+
+impl From<What> for WhatAmI {
+    fn from(what: What) -> Self {
+        match what {
+            What::Router => WhatAmI::Router,
+            What::Peer => WhatAmI::Peer,
+            What::Client => WhatAmI::Client,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LivelinessCommands {
+    #[command(about = "list currently alive tokens")]
+    Get {
+        #[arg(help = "key expression")]
+        keyexpr: String,
+        #[arg(long, help = "query timeout", default_value = "10s", value_parser = parse_duration)]
+        timeout: Duration,
+    },
+
+    #[command(about = "print tokens as they appear (PUT) and disappear (DELETE)", alias = "sub")]
+    Subscribe {
+        #[arg(help = "key expression")]
+        keyexpr: String,
+        #[arg(long, help = "also print tokens that were alive before subscribing")]
+        history: bool,
+    },
+
+    #[command(about = "declare a token and hold it until interrupted")]
+    Declare {
+        #[arg(help = "key expression")]
+        keyexpr: String,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Target {
+    All,
+    AllComplete,
+    BestMatching,
+}
+
+impl From<Target> for QueryTarget {
+    fn from(target: Target) -> Self {
+        match target {
+            Target::All => QueryTarget::All,
+            Target::AllComplete => QueryTarget::AllComplete,
+            Target::BestMatching => QueryTarget::BestMatching,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Consolidation {
+    Auto,
+    None,
+    Monotonic,
+    Latest,
+}
+
+impl From<Consolidation> for ConsolidationMode {
+    fn from(consolidation: Consolidation) -> Self {
+        match consolidation {
+            Consolidation::Auto => ConsolidationMode::Auto,
+            Consolidation::None => ConsolidationMode::None,
+            Consolidation::Monotonic => ConsolidationMode::Monotonic,
+            Consolidation::Latest => ConsolidationMode::Latest,
+        }
+    }
+}
+
+fn parse_encoding(headers: &[(String, String)]) -> anyhow::Result<Encoding> {
+    let mut encoding = Encoding::default();
+    for (key, value) in headers {
+        if key.eq_ignore_ascii_case("content-type") {
+            encoding = Encoding::from_str(value)?;
+        } else {
+            log::warn!("unknown header: {}, zenoh only supports Content-Type", key);
+        }
+    }
+    Ok(encoding)
+}
+
+fn sample_to_frame(sample: &Sample) -> Frame {
+    let mut frame = Frame {
+        topic: sample.key_expr().to_string(),
+        headers: Default::default(),
+        payload: sample.payload().to_bytes().to_vec(),
+    };
+    // pseudo-headers, only non-default values are shown except for kind
+    frame.headers.insert("Kind".to_string(), vec![sample.kind().to_string()]);
+    if sample.encoding() != &Encoding::default() {
+        frame.headers.insert("Content-Type".to_string(), vec![sample.encoding().to_string()]);
+    }
+    if let Some(timestamp) = sample.timestamp() {
+        frame.headers.insert("Timestamp".to_string(), vec![timestamp.to_string()]);
+    }
+    if sample.priority() != Priority::default() {
+        frame.headers.insert("Priority".to_string(), vec![(sample.priority() as u8).to_string()]);
+    }
+    if sample.congestion_control() != CongestionControl::default() {
+        frame.headers.insert("Congestion-Control".to_string(), vec![format!("{:?}", sample.congestion_control()).to_lowercase()]);
+    }
+    if sample.express() {
+        frame.headers.insert("Express".to_string(), vec!["true".to_string()]);
+    }
+    frame
+}
+
+fn reply_to_frame(reply: &Reply, selector: &str) -> Frame {
+    let mut frame = match reply.result() {
+        Ok(sample) => sample_to_frame(sample),
+        Err(err) => {
+            // error replies don't carry a key expression, show the one we asked for
+            let mut frame = Frame {
+                topic: selector.split('?').next().unwrap_or_default().to_owned(),
+                headers: Default::default(),
+                payload: err.payload().to_bytes().to_vec(),
+            };
+            frame.headers.insert("Reply-Error".to_string(), vec!["true".to_string()]);
+            if err.encoding() != &Encoding::default() {
+                frame.headers.insert("Content-Type".to_string(), vec![err.encoding().to_string()]);
+            }
+            frame
+        }
+    };
+    if let Some(replier_id) = reply.replier_id() {
+        frame.headers.insert("Replier-ID".to_string(), vec![replier_id.zid().to_string()]);
+    }
+    frame
+}
+
+fn query_to_frame(query: &Query) -> Frame {
+    let mut frame = Frame {
+        topic: query.key_expr().to_string(),
+        headers: Default::default(),
+        payload: query.payload().map(|p| p.to_bytes().to_vec()).unwrap_or_default(),
+    };
+    if !query.parameters().is_empty() {
+        frame.headers.insert("Parameters".to_string(), vec![query.parameters().to_string()]);
+    }
+    if let Some(encoding) = query.encoding().filter(|e| *e != &Encoding::default()) {
+        frame.headers.insert("Content-Type".to_string(), vec![encoding.to_string()]);
+    }
+    frame
+}
+
+// wildcard queryables can only reply on the key expression that was asked for
+fn reply_key(queryable_key: &KeyExpr<'static>, query: &Query) -> KeyExpr<'static> {
+    if queryable_key.is_wild() {
+        query.key_expr().clone()
+    } else {
+        queryable_key.clone()
+    }
+}
+
+// merge `key/expr` into nested objects, so the whole admin space becomes one json document
+fn insert_path(root: &mut serde_json::Map<String, serde_json::Value>, key_expr: &str, value: serde_json::Value) {
+    let mut node = root;
+    let mut chunks = key_expr.split('/').peekable();
+    while let Some(chunk) = chunks.next() {
+        if chunks.peek().is_none() {
+            match node.get_mut(chunk) {
+                // children were inserted before the parent, keep both
+                Some(serde_json::Value::Object(children)) => match value {
+                    serde_json::Value::Object(fields) => children.extend(fields),
+                    value => { children.insert("@value".to_string(), value); }
+                },
+                _ => { node.insert(chunk.to_string(), value); }
+            }
+            return;
+        }
+        let entry = node.entry(chunk).or_insert_with(|| serde_json::Value::Object(Default::default()));
+        if !entry.is_object() {
+            let value = entry.take();
+            *entry = serde_json::json!({ "@value": value });
+        }
+        node = entry.as_object_mut().unwrap();
+    }
+}
+
+fn render_tree(value: &serde_json::Value, indent: usize, out: &mut String) {
+    fn scalar(value: &serde_json::Value) -> Option<String> {
+        match value {
+            serde_json::Value::String(s) => Some(s.clone()),
+            serde_json::Value::Object(o) if o.is_empty() => Some("{}".to_string()),
+            serde_json::Value::Array(a) if a.is_empty() => Some("[]".to_string()),
+            serde_json::Value::Object(_) | serde_json::Value::Array(_) => None,
+            value => Some(value.to_string()),
+        }
+    }
+
+    let pad = " ".repeat(indent);
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields {
+                match scalar(value) {
+                    Some(s) => out.push_str(&format!("{pad}{key}: {s}\n")),
+                    None => {
+                        out.push_str(&format!("{pad}{key}:\n"));
+                        render_tree(value, indent + 2, out);
+                    }
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                match scalar(item) {
+                    Some(s) => out.push_str(&format!("{pad}- {s}\n")),
+                    None => {
+                        out.push_str(&format!("{pad}-\n"));
+                        render_tree(item, indent + 2, out);
+                    }
+                }
+            }
+        }
+        value => out.push_str(&format!("{pad}{}\n", scalar(value).unwrap_or_default())),
+    }
+}
+
+async fn wait_for_matching(publisher: &zenoh::pubsub::Publisher<'static>, timeout: Duration) -> anyhow::Result<bool> {
+    let matching_listener = publisher.matching_listener()
+        .await
+        .map_err(|err| anyhow!("matching listener failed: {}", err))?;
+
+    let matching_status = publisher.matching_status()
+        .await
+        .map_err(|err| anyhow!("matching status failed: {}", err))?;
+    if matching_status.matching() {
+        return Ok(true);
+    }
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        match tokio::time::timeout_at(deadline, matching_listener.recv_async()).await {
+            Ok(Ok(status)) => {
+                log::debug!("matching listener status: {:?}", status.matching());
+                if status.matching() {
+                    return Ok(true);
+                }
+            }
+            Ok(Err(err)) => {
+                bail!("recv failed: {}", err)
+            }
+            Err(_) => {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+/// Key expressions of tokens alive at the moment, error replies are only logged.
+async fn alive_tokens(session: &Session, keyexpr: &str, timeout: Duration) -> anyhow::Result<Vec<String>> {
+    let replies = session.liveliness().get(keyexpr)
+        .timeout(timeout)
+        .await
+        .map_err(|err| anyhow!("query failed: {}", err))?;
+    let mut tokens = vec![];
+    while let Ok(reply) = replies.recv_async().await {
+        match reply.result() {
+            Ok(sample) => tokens.push(sample.key_expr().to_string()),
+            Err(err) => {
+                log::warn!("error reply: {}", String::from_utf8_lossy(&err.payload().to_bytes()));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+pub struct ZenohPublisher {
+    publisher: zenoh::pubsub::Publisher<'static>,
+}
+
+impl Publisher for ZenohPublisher {
+    async fn publish(&self, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        let encoding = parse_encoding(headers)?;
+        self.publisher.put(payload.to_vec()).encoding(encoding).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        Ok(())
+    }
+}
 
 struct ZenohMQ {
     url: Option<String>,
     client: Session,
-    // config: Config,
 }
 
 impl Drop for ZenohMQ {
@@ -23,15 +511,15 @@ impl Drop for ZenohMQ {
 }
 
 impl MessageQueue for ZenohMQ {
-    async fn connect(addr: Option<&str>) -> anyhow::Result<Self> {
-        let mut config = zenoh::Config::default();
-        let mut url = None;
-        if let Some(addr) = addr {
-            url = Some(addr.to_owned());
-            config
-                .insert_json5("connect/endpoints", &serde_json::json!([addr]).to_string())
-                .unwrap();
-        }
+    type Options = ZenohOptions;
+    type Commands = ZenohCommands;
+    type PublishOptions = ZenohPublishOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = ZenohPublisher;
+
+    async fn connect(addr: Option<&str>, options: &ZenohOptions) -> anyhow::Result<Self> {
+        let config = options.config(addr)?;
+        let url = addr.map(|addr| addr.to_owned());
 
         let zenoh = zenoh::open(config).await
             .map_err(|e| anyhow::anyhow!(e))?;
@@ -39,7 +527,7 @@ impl MessageQueue for ZenohMQ {
         Ok(Self { url, client: zenoh })
     }
 
-    async fn info(&self) -> anyhow::Result<String> {
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
         let mut info = vec![];
         if let Some(url) = &self.url {
             info.push(("URL", url.clone()));
@@ -54,47 +542,36 @@ impl MessageQueue for ZenohMQ {
             info.push(("Connected Peer ID", peer.to_string()));
         }
 
-        Ok(format_table(&info))
+        Ok(format_info(&info, json))
     }
 
     async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
-        let mut encoding = Encoding::default();
-        for (key, value) in headers {
-            if key.eq_ignore_ascii_case("content-type") {
-                encoding = Encoding::from_str(value)?;
-            } else {
-                log::warn!("unknown header: {}, zenoh only supports Content-Type", key);
-            }
-        }
+        let encoding = parse_encoding(headers)?;
+        self.client.put(topic, payload.to_vec()).encoding(encoding).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        Ok(())
+    }
 
+    async fn publisher(&self, topic: &str, options: &ZenohPublishOptions) -> anyhow::Result<ZenohPublisher> {
         let publisher = self.client.declare_publisher(topic.to_owned())
-            .encoding(encoding)
+            .priority(options.qos.priority())
+            .express(options.qos.express)
+            .congestion_control(options.qos.congestion_control())
+            .reliability(options.reliability())
             .await
             .map_err(|err| anyhow!("declare failed: {}", err))?;
 
-        let matching_listener = publisher.matching_listener()
-            .await
-            .map_err(|err| anyhow!("matching listener failed: {}", err))?;
-
-        match tokio::time::timeout(Duration::from_secs(5), matching_listener.recv_async()).await {
-            Ok(Ok(result)) => {
-                log::debug!("matching listener status: {:?}", result.matching());
-            }
-            Ok(Err(err)) => {
-                bail!("recv failed: {}", err)
-            }
-            Err(_) => {
-                bail!("failed to find matching listeners")
-            }
+        // freshly declared publisher may not know about existing subscribers yet,
+        // so messages published right away can be lost
+        let wait = options.wait_for_subscribers;
+        if !wait.is_zero() && !wait_for_matching(&publisher, wait).await? {
+            log::warn!("no subscribers matching \"{}\" found within {:?}", topic, wait);
         }
 
-        publisher.put(payload.to_vec()).await
-            .map_err(|err| anyhow!("failed to publish: {}", err))?;
-
-        Ok(())
+        Ok(ZenohPublisher { publisher })
     }
 
-    fn subscribe(&self, topic: &str) -> impl Stream<Item = anyhow::Result<Frame>> {
+    fn subscribe(&self, topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
         let subscriber = self.client.declare_subscriber(topic.to_owned());
 
         async_stream::try_stream! {
@@ -103,50 +580,315 @@ impl MessageQueue for ZenohMQ {
             loop {
                 let sample = subscriber.recv_async().await
                     .map_err(|err| anyhow!("recv failed: {}", err))?;
-                let mut frame = Frame {
-                    topic: sample.key_expr().to_string(),
-                    headers: Default::default(),
-                    payload: sample.payload().to_bytes().to_vec(),
-                };
-                if sample.encoding() != &Encoding::default() {
-                    frame.headers.insert("Content-Type".to_string(), vec![sample.encoding().to_string()]);
-                }
-                yield frame;
+                yield sample_to_frame(&sample);
             }
         }
     }
 
     async fn request(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<Frame> {
-        let mut encoding = Encoding::default();
-        for (key, value) in headers {
-            if key.eq_ignore_ascii_case("content-type") {
-                encoding = Encoding::from_str(value)?;
-            } else {
-                log::warn!("unknown header: {}, zenoh only supports Content-Type", key);
-            }
-        }
-
-        let querier = self.client.declare_querier(topic.to_owned())
+        let encoding = parse_encoding(headers)?;
+        let replies = self.client.get(topic)
             .target(QueryTarget::BestMatching)
+            .payload(payload.to_vec())
+            .encoding(encoding)
             .await
-            .map_err(|err| anyhow!("declare failed: {}", err))?;
-        let replies = querier.get().payload(payload.to_vec()).encoding(encoding).await
             .map_err(|err| anyhow!("query failed: {}", err))?;
         let reply = replies.recv_async().await
             .map_err(|err| anyhow!("recv failed: {}", err))?;
         let result = reply.result().map_err(|err| anyhow!("result failed: {}", err))?;
-        let mut frame = Frame {
-            topic: result.key_expr().to_string(),
-            headers: Default::default(),
-            payload: result.payload().to_bytes().to_vec(),
-        };
-        if result.encoding() != &Encoding::default() {
-            frame.headers.insert("Content-Type".to_string(), vec![result.encoding().to_string()]);
+        Ok(sample_to_frame(result))
+    }
+
+    async fn command(addr: Option<&str>, options: &ZenohOptions, command: ZenohCommands) -> anyhow::Result<()> {
+        match command {
+            ZenohCommands::Delete { keyexpr, qos } => {
+                let mq = Self::connect(addr, options).await?;
+                mq.client.delete(&keyexpr)
+                    .priority(qos.priority())
+                    .express(qos.express)
+                    .congestion_control(qos.congestion_control())
+                    .await
+                    .map_err(|err| anyhow!("failed to delete: {}", err))?;
+                log::info!("deleted \"{}\"", keyexpr);
+            }
+            ZenohCommands::Get { selector, data, header, target, consolidation, timeout, translate } => {
+                let mq = Self::connect(addr, options).await?;
+                let mut query = mq.client.get(&selector)
+                    .target(target.into())
+                    .consolidation(ConsolidationMode::from(consolidation))
+                    .timeout(timeout)
+                    .encoding(parse_encoding(&header)?);
+                if let Some(data) = data {
+                    query = query.payload(data);
+                }
+                let replies = query.await
+                    .map_err(|err| anyhow!("query failed: {}", err))?;
+
+                // channel is closed once all queryables replied or timeout expired
+                let mut idx = 0;
+                while let Ok(reply) = replies.recv_async().await {
+                    idx += 1;
+                    print_data(idx, &reply_to_frame(&reply, &selector), &translate).await?;
+                }
+                log::info!("received {} replies", idx);
+            }
+            ZenohCommands::Reply { keyexpr, data, header, complete, translate } => {
+                let mq = Self::connect(addr, options).await?;
+                let data = data_or_stdin(data)?;
+                let encoding = parse_encoding(&header)?;
+                let queryable = mq.client.declare_queryable(&keyexpr)
+                    .complete(complete)
+                    .await
+                    .map_err(|err| anyhow!("declare failed: {}", err))?;
+
+                let mut idx = 0;
+                loop {
+                    let query = queryable.recv_async().await
+                        .map_err(|err| anyhow!("recv failed: {}", err))?;
+                    idx += 1;
+                    print_data(idx, &query_to_frame(&query), &translate).await?;
+                    query.reply(reply_key(queryable.key_expr(), &query), data.clone()).encoding(encoding.clone()).await
+                        .map_err(|err| anyhow!("reply failed: {}", err))?;
+                }
+            }
+            ZenohCommands::Liveliness(LivelinessCommands::Get { keyexpr, timeout }) => {
+                let mq = Self::connect(addr, options).await?;
+                let tokens = alive_tokens(&mq.client, &keyexpr, timeout).await?;
+                for token in &tokens {
+                    std::io::stdout().write_all(format!("{}\n", token).as_bytes())?;
+                }
+                std::io::stdout().flush()?;
+                log::info!("found {} alive tokens", tokens.len());
+            }
+            ZenohCommands::Liveliness(LivelinessCommands::Subscribe { keyexpr, history }) => {
+                let mq = Self::connect(addr, options).await?;
+                let subscriber = mq.client.liveliness().declare_subscriber(&keyexpr)
+                    .history(history)
+                    .await
+                    .map_err(|err| anyhow!("declare failed: {}", err))?;
+                loop {
+                    let sample = subscriber.recv_async().await
+                        .map_err(|err| anyhow!("recv failed: {}", err))?;
+                    std::io::stdout().write_all(format!("{} {}\n", sample.kind(), sample.key_expr()).as_bytes())?;
+                    std::io::stdout().flush()?;
+                }
+            }
+            ZenohCommands::Liveliness(LivelinessCommands::Declare { keyexpr }) => {
+                let mq = Self::connect(addr, options).await?;
+                let _token = mq.client.liveliness().declare_token(&keyexpr)
+                    .await
+                    .map_err(|err| anyhow!("declare failed: {}", err))?;
+                log::info!("declared liveliness token \"{}\", press Ctrl-C to undeclare", keyexpr);
+                std::future::pending::<()>().await;
+            }
+            ZenohCommands::Scout { duration, what } => {
+                let matcher = what.into_iter()
+                    .fold(WhatAmIMatcher::empty(), |matcher, what| matcher | WhatAmI::from(what));
+                let scout = zenoh::scout(matcher, options.config(addr)?).await
+                    .map_err(|err| anyhow!("scout failed: {}", err))?;
+
+                // the same node may answer several times (e.g. on every interface)
+                let mut seen = std::collections::HashSet::new();
+                let deadline = tokio::time::Instant::now() + duration;
+                while let Ok(Ok(hello)) = tokio::time::timeout_at(deadline, scout.recv_async()).await {
+                    if !seen.insert(hello.zid()) {
+                        continue;
+                    }
+                    let mut info = vec![];
+                    info.push(("ZID", hello.zid().to_string()));
+                    info.push(("What", hello.whatami().to_string()));
+                    for locator in hello.locators() {
+                        info.push(("Locator", locator.to_string()));
+                    }
+                    info.push(("", String::new()));
+                    std::io::stdout().write_all(format_table(&info).as_bytes())?;
+                    std::io::stdout().flush()?;
+                }
+                scout.stop();
+                log::info!("found {} nodes", seen.len());
+            }
+            ZenohCommands::Admin { selector, json, timeout } => {
+                let mq = Self::connect(addr, options).await?;
+                let replies = mq.client.get(&selector)
+                    .target(QueryTarget::All)
+                    .consolidation(ConsolidationMode::None)
+                    .timeout(timeout)
+                    .await
+                    .map_err(|err| anyhow!("query failed: {}", err))?;
+
+                let mut root = serde_json::Map::new();
+                let mut count = 0;
+                while let Ok(reply) = replies.recv_async().await {
+                    let sample = match reply.result() {
+                        Ok(sample) => sample,
+                        Err(err) => {
+                            log::warn!("error reply: {}", String::from_utf8_lossy(&err.payload().to_bytes()));
+                            continue;
+                        }
+                    };
+                    let payload = sample.payload().to_bytes();
+                    let value = serde_json::from_slice(&payload)
+                        .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(&payload).into_owned()));
+                    insert_path(&mut root, sample.key_expr().as_str(), value);
+                    count += 1;
+                }
+                log::info!("received {} admin space keys", count);
+
+                let root = serde_json::Value::Object(root);
+                let mut out = if json {
+                    serde_json::to_string_pretty(&root)?
+                } else {
+                    let mut out = String::new();
+                    render_tree(&root, 0, &mut out);
+                    out
+                };
+                if !out.ends_with('\n') {
+                    out.push('\n');
+                }
+                std::io::stdout().write_all(out.as_bytes())?;
+                std::io::stdout().flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    // zenoh reconnects on its own without reporting it, so connectivity is polled instead;
+    // every check without any routers or peers after losing them counts as a failed attempt
+    fn connection_events(&self) -> impl Stream<Item = ConnectionEvent> {
+        async_stream::stream! {
+            let mut connected = None;
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                let info = self.client.info();
+                let now = info.routers_zid().await.next().is_some() || info.peers_zid().await.next().is_some();
+                match (connected, now) {
+                    (Some(true), false) => yield ConnectionEvent::Disconnected {
+                        code: None,
+                        reason: "lost all routers and peers".to_string(),
+                    },
+                    (Some(false), false) => yield ConnectionEvent::ReconnectFailed {
+                        error: "no routers or peers reachable".to_string(),
+                    },
+                    (Some(false), true) => yield ConnectionEvent::Connected,
+                    _ => {}
+                }
+                // never connected, nothing to report
+                if connected.is_some() || now {
+                    connected = Some(now);
+                }
+            }
         }
-        Ok(frame)
     }
 }
 
 pub async fn run(args: impl Iterator<Item = String>) {
     crate::cli::run::<ZenohMQ>(args).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_options(listen: Vec<String>) -> ZenohOptions {
+        ZenohOptions { zenoh_config: None, mode: Some(Mode::Peer), listen, no_scouting: true, cfg: vec![] }
+    }
+
+    /// Two peer sessions connected to each other over loopback, no router involved.
+    async fn peers() -> (ZenohMQ, ZenohMQ) {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let endpoint = format!("tcp/127.0.0.1:{}", port);
+        let first = ZenohMQ::connect(None, &peer_options(vec![endpoint.clone()])).await.unwrap();
+        let second = ZenohMQ::connect(Some(&endpoint), &peer_options(vec![])).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while first.client.info().peers_zid().await.next().is_none() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }).await.unwrap();
+        (first, second)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn get_and_reply() {
+        let (replier, requester) = peers().await;
+        let queryable = replier.client.declare_queryable("demo/**").await.unwrap();
+        let replies = tokio::spawn(async move {
+            let mut frames = vec![];
+            for data in ["pong", "again"] {
+                let query = queryable.recv_async().await.unwrap();
+                frames.push(query_to_frame(&query));
+                query.reply(reply_key(queryable.key_expr(), &query), data).await.unwrap();
+            }
+            frames
+        });
+
+        // queries sent before the queryable is known to the other peer get no replies
+        let querier = requester.client.declare_querier("demo/**").await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !querier.matching_status().await.unwrap().matching() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }).await.unwrap();
+
+        let headers = [("Content-Type".to_string(), "text/plain".to_string())];
+        let frame = requester.request("demo/a", &headers, b"ping").await.unwrap();
+        assert_eq!(frame.topic, "demo/a");
+        assert_eq!(frame.payload, b"pong");
+
+        let selector = "demo/b?x=1";
+        let reply = requester.client.get(selector).await.unwrap().recv_async().await.unwrap();
+        let frame = reply_to_frame(&reply, selector);
+        assert_eq!(frame.topic, "demo/b");
+        assert_eq!(frame.payload, b"again");
+        assert_eq!(frame.headers["Replier-ID"], [replier.client.zid().to_string()]);
+
+        let queries = replies.await.unwrap();
+        assert_eq!(queries[0].payload, b"ping");
+        assert_eq!(queries[0].headers["Content-Type"], ["text/plain"]);
+        assert_eq!(queries[1].topic, "demo/b");
+        assert_eq!(queries[1].headers["Parameters"], ["x=1"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn liveliness_tokens() {
+        let (owner, observer) = peers().await;
+        let subscriber = observer.client.liveliness().declare_subscriber("fleet/**").await.unwrap();
+        let token = owner.client.liveliness().declare_token("fleet/robot1").await.unwrap();
+
+        let sample = tokio::time::timeout(Duration::from_secs(5), subscriber.recv_async()).await.unwrap().unwrap();
+        let frame = sample_to_frame(&sample);
+        assert_eq!((frame.topic.as_str(), frame.headers["Kind"][0].as_str()), ("fleet/robot1", "PUT"));
+        let tokens = alive_tokens(&observer.client, "fleet/**", Duration::from_secs(5)).await.unwrap();
+        assert_eq!(tokens, ["fleet/robot1"]);
+
+        token.undeclare().await.unwrap();
+        let sample = tokio::time::timeout(Duration::from_secs(5), subscriber.recv_async()).await.unwrap().unwrap();
+        let frame = sample_to_frame(&sample);
+        assert_eq!((frame.topic.as_str(), frame.headers["Kind"][0].as_str()), ("fleet/robot1", "DELETE"));
+        assert!(alive_tokens(&observer.client, "fleet/**", Duration::from_secs(5)).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn admin_space_tree() {
+        let mut root = serde_json::Map::new();
+        insert_path(&mut root, "@/abc/router/subscriber/demo/x", serde_json::json!(null));
+        insert_path(&mut root, "@/abc/router", serde_json::json!({ "zid": "abc", "locators": ["tcp/127.0.0.1:7447"] }));
+        insert_path(&mut root, "@/abc/router/status", serde_json::json!("ok"));
+
+        let mut out = String::new();
+        render_tree(&serde_json::Value::Object(root), 0, &mut out);
+        assert_eq!(out, [
+            "@:",
+            "  abc:",
+            "    router:",
+            "      locators:",
+            "        - tcp/127.0.0.1:7447",
+            "      status: ok",
+            "      subscriber:",
+            "        demo:",
+            "          x: null",
+            "      zid: abc",
+            "",
+        ].join("\n"));
+    }
+}
@@ -0,0 +1,293 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use clap::Args;
+use futures_util::{Stream, StreamExt};
+use tokio::sync::Mutex;
+use zeromq::{PubSocket, ReqSocket, Socket, SocketEvent, SocketRecv, SocketSend, SubSocket, ZmqMessage};
+
+use crate::cli::{NoCommands, NoOptions, parse_duration, parse_header};
+use crate::mqtrait::{Frame, MessageQueue, Publisher};
+use crate::utils::format_info;
+
+// subscriptions are sent by peers right after the handshake
+const SUBSCRIPTION_DELAY: Duration = Duration::from_millis(100);
+
+// --wait-for-subscribers default, also used when publishing without publish options
+const WAIT_FOR_SUBSCRIBERS: Duration = Duration::from_secs(5);
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "ZeroMQ Options")]
+pub struct ZmqOptions {
+    #[arg(global = true, long, help = "req reply timeout", default_value = "10s", value_parser = parse_duration)]
+    timeout: Duration,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "ZeroMQ Publish Options")]
+pub struct ZmqPublishOptions {
+    #[arg(long, help = "wait for the first peer up to this duration (0s to disable)", default_value = "5s", value_parser = parse_duration)]
+    wait_for_subscribers: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Bind,
+    Connect,
+}
+
+/// Endpoint and optional `?bind` or `?connect` flag.
+fn parse_endpoint(url: &str) -> anyhow::Result<(String, Option<Mode>)> {
+    let (endpoint, mode) = match url.split_once('?') {
+        Some((endpoint, "bind")) => (endpoint, Some(Mode::Bind)),
+        Some((endpoint, "connect")) => (endpoint, Some(Mode::Connect)),
+        Some((_, flag)) => bail!("unknown url flag \"{}\", expected bind or connect", flag),
+        None => (url, None),
+    };
+    Ok((endpoint.to_owned(), mode))
+}
+
+/// Channel goes first, so PUB sockets can filter by it, then payload and headers.
+/// Empty channel without headers is sent as payload only, as `message_to_frame` expects.
+fn build_message(topic: &str, headers: &[(String, String)], payload: &[u8]) -> ZmqMessage {
+    let mut message = ZmqMessage::from(payload.to_vec());
+    if !topic.is_empty() || !headers.is_empty() {
+        message.push_front(topic.as_bytes().to_vec().into());
+    }
+    for (key, value) in headers {
+        message.push_back(format!("{}: {}", key, value).into_bytes().into());
+    }
+    message
+}
+
+/// Single-frame messages are payload only, otherwise frames are topic, payload and headers.
+fn message_to_frame(filter: &str, message: ZmqMessage) -> Frame {
+    let mut frames = message.into_vecdeque();
+    let mut frame = Frame {
+        topic: filter.to_owned(),
+        headers: Default::default(),
+        payload: vec![],
+    };
+    if frames.len() > 1 {
+        frame.topic = String::from_utf8_lossy(&frames.pop_front().unwrap_or_default()).into_owned();
+    }
+    frame.payload = frames.pop_front().unwrap_or_default().to_vec();
+    for (i, extra) in frames.into_iter().enumerate() {
+        let text = String::from_utf8_lossy(&extra);
+        let (key, value) = parse_header(&text).unwrap_or_else(|_| (format!("Frame-{}", i + 2), text.into_owned()));
+        frame.headers.entry(key).or_default().push(value);
+    }
+    frame
+}
+
+struct ZmqMQ {
+    url: String,
+    endpoint: String,
+    mode: Option<Mode>,
+    options: ZmqOptions,
+}
+
+impl ZmqMQ {
+    async fn open<S: Socket>(&self, mut socket: S, default: Mode) -> anyhow::Result<S> {
+        match self.mode.unwrap_or(default) {
+            Mode::Bind => {
+                let endpoint = socket.bind(&self.endpoint).await
+                    .map_err(|err| anyhow!("failed to bind {}: {}", self.endpoint, err))?;
+                log::debug!("bound to {}", endpoint);
+            }
+            Mode::Connect => {
+                // retries until the peer is up
+                socket.connect(&self.endpoint).await
+                    .map_err(|err| anyhow!("failed to connect to {}: {}", self.endpoint, err))?;
+                log::debug!("connected to {}", self.endpoint);
+            }
+        }
+        Ok(socket)
+    }
+
+    async fn pub_socket(&self, wait: Duration) -> anyhow::Result<PubSocket> {
+        let mut socket = PubSocket::new();
+        let mut monitor = socket.monitor();
+        let socket = self.open(socket, Mode::Bind).await?;
+
+        // messages sent before peers subscribe are dropped
+        if !wait.is_zero() {
+            let peer = tokio::time::timeout(wait, async {
+                while let Some(event) = monitor.next().await {
+                    if let SocketEvent::Accepted(..) | SocketEvent::Connected(..) = event {
+                        return true;
+                    }
+                }
+                false
+            }).await;
+            match peer {
+                Ok(true) => tokio::time::sleep(SUBSCRIPTION_DELAY).await,
+                _ => log::warn!("no peers connected within {:?}", wait),
+            }
+        }
+        Ok(socket)
+    }
+}
+
+pub struct ZmqPublisher {
+    topic: String,
+    socket: Mutex<PubSocket>,
+}
+
+impl Publisher for ZmqPublisher {
+    async fn publish(&self, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        self.socket.lock().await.send(build_message(&self.topic, headers, payload)).await
+            .map_err(|err| anyhow!("failed to publish: {}", err))?;
+        Ok(())
+    }
+}
+
+impl MessageQueue for ZmqMQ {
+    type Options = ZmqOptions;
+    type Commands = NoCommands;
+    type PublishOptions = ZmqPublishOptions;
+    type SubscribeOptions = NoOptions;
+    type Publisher<'a> = ZmqPublisher;
+
+    async fn connect(addr: Option<&str>, options: &ZmqOptions) -> anyhow::Result<Self> {
+        let url = addr.unwrap_or("tcp://localhost:5555").to_owned();
+        let (endpoint, mode) = parse_endpoint(&url)?;
+        Ok(Self { url, endpoint, mode, options: options.clone() })
+    }
+
+    async fn info(&self, json: bool) -> anyhow::Result<String> {
+        // there is no server to ask, only show how the endpoint is used
+        let mode = |default| match self.mode.unwrap_or(default) {
+            Mode::Bind => "bind",
+            Mode::Connect => "connect",
+        };
+        let info = vec![
+            ("URL", self.url.clone()),
+            ("Endpoint", self.endpoint.clone()),
+            ("Pub", format!("PUB socket, {}", mode(Mode::Bind))),
+            ("Sub", format!("SUB socket, {}", mode(Mode::Connect))),
+            ("Req", format!("REQ socket, {}", mode(Mode::Connect))),
+        ];
+        Ok(format_info(&info, json))
+    }
+
+    async fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        let options = ZmqPublishOptions { wait_for_subscribers: WAIT_FOR_SUBSCRIBERS };
+        self.publisher(topic, &options).await?.publish(headers, payload).await
+    }
+
+    async fn publisher(&self, topic: &str, options: &ZmqPublishOptions) -> anyhow::Result<ZmqPublisher> {
+        let socket = self.pub_socket(options.wait_for_subscribers).await?;
+        Ok(ZmqPublisher { topic: topic.to_owned(), socket: Mutex::new(socket) })
+    }
+
+    fn subscribe(&self, topic: &str, _options: &NoOptions) -> impl Stream<Item = anyhow::Result<Frame>> {
+        let topic = topic.to_owned();
+
+        async_stream::try_stream! {
+            let mut socket = self.open(SubSocket::new(), Mode::Connect).await?;
+            // empty channel subscribes to everything
+            socket.subscribe(&topic).await
+                .map_err(|err| anyhow!("subscription failed: {}", err))?;
+            loop {
+                let message = socket.recv().await
+                    .map_err(|err| anyhow!("failed to receive: {}", err))?;
+                yield message_to_frame(&topic, message);
+            }
+        }
+    }
+
+    async fn request(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<Frame> {
+        let mut socket = self.open(ReqSocket::new(), Mode::Connect).await?;
+        socket.send(build_message(topic, headers, payload)).await
+            .map_err(|err| anyhow!("failed to send request: {}", err))?;
+        let reply = tokio::time::timeout(self.options.timeout, socket.recv()).await
+            .map_err(|_| anyhow!("no reply within {:?}", self.options.timeout))?
+            .map_err(|err| anyhow!("failed to receive reply: {}", err))?;
+        Ok(message_to_frame(topic, reply))
+    }
+
+    async fn command(_addr: Option<&str>, _options: &ZmqOptions, command: NoCommands) -> anyhow::Result<()> {
+        match command {}
+    }
+}
+
+pub async fn run(args: impl Iterator<Item = String>) {
+    crate::cli::run::<ZmqMQ>(args).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use zeromq::RepSocket;
+
+    use super::*;
+
+    fn options() -> ZmqOptions {
+        ZmqOptions { timeout: Duration::from_secs(5) }
+    }
+
+    #[test]
+    fn endpoint_flags() {
+        assert_eq!(parse_endpoint("tcp://0.0.0.0:5555?bind").unwrap(), ("tcp://0.0.0.0:5555".to_string(), Some(Mode::Bind)));
+        assert_eq!(parse_endpoint("ipc:///tmp/feed.sock").unwrap(), ("ipc:///tmp/feed.sock".to_string(), None));
+        assert!(parse_endpoint("tcp://localhost:5555?listen").is_err());
+    }
+
+    #[test]
+    fn empty_channel_with_headers() {
+        let headers = [("Source".to_string(), "test".to_string())];
+        let frame = message_to_frame("", build_message("", &headers, b"data"));
+        assert_eq!(frame.topic, "");
+        assert_eq!(frame.payload, b"data");
+        assert_eq!(frame.headers["Source"], ["test"]);
+
+        let frame = message_to_frame("", build_message("", &[], b"data"));
+        assert_eq!(frame.payload, b"data");
+        assert!(frame.headers.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pub_sub_ipc() {
+        let path = std::env::temp_dir().join(format!("mqcat-test-{}.sock", std::process::id()));
+        let url = format!("ipc://{}", path.display());
+        let publisher = ZmqMQ::connect(Some(&format!("{}?bind", url)), &options()).await.unwrap();
+        let subscriber = ZmqMQ::connect(Some(&format!("{}?connect", url)), &options()).await.unwrap();
+
+        // publisher binds and waits for the subscriber to connect
+        let publisher = tokio::spawn(async move { publisher.publisher("prices.eur", &ZmqPublishOptions { wait_for_subscribers: WAIT_FOR_SUBSCRIBERS }).await.unwrap() });
+        tokio::time::sleep(SUBSCRIPTION_DELAY).await;
+        let received = tokio::spawn(async move {
+            let mut stream = Box::pin(subscriber.subscribe("prices.", &NoOptions {}));
+            stream.next().await.unwrap().unwrap()
+        });
+        let publisher = publisher.await.unwrap();
+        let headers = [("Source".to_string(), "test".to_string())];
+        publisher.publish(&headers, b"1.08").await.unwrap();
+
+        let frame = tokio::time::timeout(Duration::from_secs(5), received).await.unwrap().unwrap();
+        assert_eq!(frame.topic, "prices.eur");
+        assert_eq!(frame.payload, b"1.08");
+        assert_eq!(frame.headers["Source"], ["test"]);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn req_rep_ipc() {
+        let path = std::env::temp_dir().join(format!("mqcat-test-rep-{}.sock", std::process::id()));
+        let url = format!("ipc://{}", path.display());
+        let mut rep = RepSocket::new();
+        rep.bind(&url).await.unwrap();
+        tokio::spawn(async move {
+            let request = rep.recv().await.unwrap();
+            let mut reply = request.into_vecdeque();
+            reply.make_contiguous().reverse();
+            rep.send(ZmqMessage::try_from(reply).unwrap()).await.unwrap();
+        });
+
+        let mq = ZmqMQ::connect(Some(&url), &options()).await.unwrap();
+        let reply = mq.request("", &[], b"ping").await.unwrap();
+        assert_eq!(reply.payload, b"ping");
+        assert!(reply.headers.is_empty());
+        let _ = std::fs::remove_file(path);
+    }
+}
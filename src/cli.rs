@@ -4,8 +4,8 @@ use std::pin::pin;
 use std::time::Duration;
 
 use anyhow::Context;
-use clap::Parser;
 use clap::builder::Styles;
+use clap::{Args, Parser, Subcommand};
 use clap::builder::styling::AnsiColor;
 use futures_util::StreamExt;
 use tokio::io::AsyncWriteExt;
@@ -13,16 +13,14 @@ use tokio::sync::mpsc::error::TrySendError;
 use tracing_subscriber::filter;
 use tracing_subscriber::prelude::*;
 
-use crate::mqtrait::{Frame, MessageQueue};
+use crate::mqtrait::{ConnectionEvent, Frame, MessageQueue, Publisher};
 
 #[derive(Parser, Debug)]
 #[command(disable_help_subcommand = true)]
 #[command(disable_help_flag = true)]
 #[command(disable_version_flag = true)]
 #[command(styles = get_styles())]
-pub struct BaseArgs/*<T: Args>*/ {
-    // #[clap(flatten)]
-    // pub args: T,
+pub struct BaseArgs<T: Args, C: Subcommand, P: Args, S: Args> {
     #[arg(global = true, short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
     /// increase logging verbosity
     verbose: u8,
@@ -35,17 +33,22 @@ pub struct BaseArgs/*<T: Args>*/ {
     #[arg(global = true, short = 'V', long)]
     /// print version and build info
     version: bool,
+    #[command(flatten)]
+    options: T,
     /// server address
     url: String,
     #[command(subcommand)]
     /// command (pub, sub, etc.)
-    command: Option<Commands>,
+    command: Option<Commands<C, P, S>>,
 }
 
 #[derive(Parser, Debug)]
-enum Commands {
+enum Commands<C: Subcommand, P: Args, S: Args> {
     #[command(about = "show server info")]
-    Info,
+    Info {
+        #[arg(long, help = "print info as json")]
+        json: bool,
+    },
 
     #[command(about = "publish a message to a channel", alias = "pub")]
     Publish {
@@ -59,6 +62,8 @@ enum Commands {
         count: u32,
         #[arg(long, help = "sleep between messages", default_value = "0", value_parser = parse_duration)]
         sleep: Duration,
+        #[command(flatten)]
+        options: P,
     },
 
     #[command(about = "subscribe to a channel", alias = "sub")]
@@ -67,6 +72,12 @@ enum Commands {
         channel: String,
         #[arg(long, help = "decode the message by passing it through a given command")]
         translate: Option<String>,
+        #[arg(long, help = "keep subscription alive across disconnects, marking every gap in the output")]
+        reconnect: bool,
+        #[arg(long, help = "exit after this many failed reconnect attempts in a row (unlimited if not set)", requires = "reconnect")]
+        reconnect_attempts: Option<u32>,
+        #[command(flatten)]
+        options: S,
     },
 
     #[command(about = "request a message from a channel", alias = "req")]
@@ -82,9 +93,12 @@ enum Commands {
         #[arg(long, help = "decode the message by passing it through a given command")]
         translate: Option<String>,
     },
+
+    #[command(flatten)]
+    Backend(C),
 }
 
-fn parse_header(s: &str) -> Result<(String, String), String> {
+pub(crate) fn parse_header(s: &str) -> Result<(String, String), String> {
     let parts = s.splitn(2, ':').collect::<Vec<&str>>();
     if parts.len() != 2 {
         return Err("header must be in the format of \"key: value\"".to_string());
@@ -92,7 +106,7 @@ fn parse_header(s: &str) -> Result<(String, String), String> {
     Ok((parts[0].trim().to_string(), parts[1].trim().to_string()))
 }
 
-fn parse_duration(s: &str) -> Result<Duration, String> {
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
     let duration = go_parse_duration::parse_duration(s)
         .map_err(|go_parse_duration::Error::ParseError(e)| e)?;
     if duration < 0 {
@@ -101,6 +115,14 @@ fn parse_duration(s: &str) -> Result<Duration, String> {
     Ok(Duration::from_nanos(duration as u64))
 }
 
+// placeholder for backends without any specific options, publish or subscribe options
+#[derive(Args, Debug)]
+pub struct NoOptions {}
+
+// placeholder for backends without any specific commands
+#[derive(Subcommand, Debug)]
+pub enum NoCommands {}
+
 pub fn get_styles() -> Styles {
     // clap v3 styles, see
     // https://stackoverflow.com/questions/74068168/clap-rs-not-printing-colors-during-help
@@ -176,11 +198,11 @@ pub fn setup_logging(verbose: u8, quiet: u8) {
         .init();
 }
 
-pub async fn init(
+pub async fn init<T: Args, C: Subcommand, P: Args, S: Args>(
     args: impl Iterator<Item = String>,
-    run_app: impl AsyncFnOnce(BaseArgs) -> anyhow::Result<()>,
+    run_app: impl AsyncFnOnce(BaseArgs<T, C, P, S>) -> anyhow::Result<()>,
 ) {
-    let args = BaseArgs::parse_from(args);
+    let args = BaseArgs::<T, C, P, S>::parse_from(args);
     setup_logging(args.verbose, args.quiet);
 
     if args.version {
@@ -223,7 +245,7 @@ async fn translate_data(data: &[u8], translate: &str) -> anyhow::Result<Vec<u8>>
     Ok(result.stdout)
 }
 
-async fn print_data(idx: u32, frame: &Frame, translate: &Option<String>) -> anyhow::Result<()> {
+pub(crate) async fn print_data(idx: u32, frame: &Frame, translate: &Option<String>) -> anyhow::Result<()> {
     std::io::stdout().write_all(
         format!("[#{idx}] Received on \"{}\" ({} bytes)\n", frame.topic, frame.payload.len()).as_bytes()
     )?;
@@ -254,6 +276,99 @@ async fn print_data(idx: u32, frame: &Frame, translate: &Option<String>) -> anyh
     Ok(())
 }
 
+fn print_marker(text: &str) -> anyhow::Result<()> {
+    std::io::stdout().write_all(format!("--- {} ---\n\n", text).as_bytes())?;
+    std::io::stdout().flush()?;
+    Ok(())
+}
+
+// resubscribes whenever subscription fails, until `max_attempts` attempts in a row fail
+async fn subscribe_with_reconnect<Q: MessageQueue>(
+    mq: &Q,
+    channel: &str,
+    options: &Q::SubscribeOptions,
+    translate: &Option<String>,
+    max_attempts: Option<u32>,
+) -> anyhow::Result<()> {
+    let mut idx = 0;
+    let mut failed = 0;
+    let mut disconnected_at: Option<std::time::Instant> = None;
+    let mut events = pin!(mq.connection_events());
+
+    let give_up = |failed: u32| max_attempts.is_some_and(|max_attempts| failed >= max_attempts);
+    let reconnected = |disconnected_at: &mut Option<std::time::Instant>| -> anyhow::Result<()> {
+        if let Some(since) = disconnected_at.take() {
+            print_marker(&format!("reconnected after {:.1?}", since.elapsed()))?;
+        }
+        Ok(())
+    };
+
+    loop {
+        let stream = mq.subscribe(channel, options);
+        let mut stream = pin!(stream);
+        let err = loop {
+            tokio::select! {
+                msg = stream.next() => match msg {
+                    Some(Ok(frame)) => {
+                        failed = 0;
+                        reconnected(&mut disconnected_at)?;
+                        idx += 1;
+                        print_data(idx, &frame, translate).await?;
+                    }
+                    Some(Err(err)) => break err,
+                    None => break anyhow::anyhow!("subscription closed"),
+                },
+                Some(event) = events.next() => match event {
+                    ConnectionEvent::Connected => {
+                        failed = 0;
+                        reconnected(&mut disconnected_at)?;
+                    }
+                    ConnectionEvent::Disconnected { code, reason } => {
+                        if disconnected_at.is_none() {
+                            disconnected_at = Some(std::time::Instant::now());
+                            match code {
+                                Some(code) => print_marker(&format!("disconnected: {} (code={})", reason, code))?,
+                                None => print_marker(&format!("disconnected: {}", reason))?,
+                            }
+                        }
+                    }
+                    ConnectionEvent::ReconnectFailed { error } => {
+                        failed += 1;
+                        log::warn!("reconnect attempt {} failed: {}", failed, error);
+                        if give_up(failed) {
+                            anyhow::bail!("giving up after {} failed reconnect attempts: {}", failed, error);
+                        }
+                    }
+                },
+            }
+        };
+
+        // subscription itself is gone (e.g. unsubscribed by server), so it has to be re-created
+        if disconnected_at.is_none() {
+            disconnected_at = Some(std::time::Instant::now());
+            print_marker(&format!("subscription lost: {}", err))?;
+        }
+        failed += 1;
+        if give_up(failed) {
+            anyhow::bail!("giving up after {} failed reconnect attempts: {}", failed, err);
+        }
+        log::warn!("resubscribing after failed attempt {}: {}", failed, err);
+        tokio::time::sleep(Duration::from_millis(500) * failed.min(20)).await;
+    }
+}
+
+pub(crate) fn data_or_stdin(data: Option<String>) -> anyhow::Result<Vec<u8>> {
+    if let Some(data) = data {
+        Ok(data.into())
+    } else {
+        log::info!("reading data from stdin...");
+        let mut buffer = Vec::new();
+        std::io::stdin().read_to_end(&mut buffer)?;
+        // log::info!("read {} bytes from stdin", buffer.len());
+        Ok(buffer)
+    }
+}
+
 pub async fn run<Q: MessageQueue>(args: impl Iterator<Item = String>) {
     fn url_or_empty(url: &str) -> Option<&str> {
         if url.is_empty() {
@@ -263,41 +378,34 @@ pub async fn run<Q: MessageQueue>(args: impl Iterator<Item = String>) {
         }
     }
 
-    fn data_or_stdin(data: Option<String>) -> anyhow::Result<Vec<u8>> {
-        if let Some(data) = data {
-            Ok(data.into())
-        } else {
-            log::info!("reading data from stdin...");
-            let mut buffer = Vec::new();
-            std::io::stdin().read_to_end(&mut buffer)?;
-            // log::info!("read {} bytes from stdin", buffer.len());
-            Ok(buffer)
-        }
-    }
-
-    init(args, |args: BaseArgs| async move {
+    init(args, |args: BaseArgs<Q::Options, Q::Commands, Q::PublishOptions, Q::SubscribeOptions>| async move {
         match args.command {
-            Some(Commands::Info) => {
-                let mq = Q::connect(url_or_empty(&args.url)).await?;
-                let info = mq.info().await?;
+            Some(Commands::Info { json }) => {
+                let mq = Q::connect(url_or_empty(&args.url), &args.options).await?;
+                let info = mq.info(json).await?;
                 std::io::stdout().write_all(info.as_bytes())?;
                 std::io::stdout().flush()?;
             }
-            Some(Commands::Publish { channel, data, header, count, sleep }) => {
-                let mq = Q::connect(url_or_empty(&args.url)).await?;
+            Some(Commands::Publish { channel, data, header, count, sleep, options }) => {
+                let mq = Q::connect(url_or_empty(&args.url), &args.options).await?;
                 let data = data_or_stdin(data)?;
+                let publisher = mq.publisher(&channel, &options).await?;
                 for n in 0..count {
                     if n > 0 {
                         tokio::time::sleep(sleep).await;
                     }
-                    mq.publish(&channel, &header, &data).await?;
+                    publisher.publish(&header, &data).await?;
                     log::info!("published {} bytes to \"{}\"", data.len(), channel);
                 }
             }
-            Some(Commands::Subscribe { channel, translate }) => {
+            Some(Commands::Subscribe { channel, translate, reconnect: true, reconnect_attempts, options }) => {
+                let mq = Q::connect(url_or_empty(&args.url), &args.options).await?;
+                subscribe_with_reconnect(&mq, &channel, &options, &translate, reconnect_attempts).await?;
+            }
+            Some(Commands::Subscribe { channel, translate, options, .. }) => {
                 let mut idx = 0;
-                let mq = Q::connect(url_or_empty(&args.url)).await?;
-                let stream = mq.subscribe(&channel);
+                let mq = Q::connect(url_or_empty(&args.url), &args.options).await?;
+                let stream = mq.subscribe(&channel, &options);
                 let mut stream = pin!(stream);
                 while let Some(msg) = stream.next().await {
                     let frame = msg?;
@@ -306,7 +414,7 @@ pub async fn run<Q: MessageQueue>(args: impl Iterator<Item = String>) {
                 }
             }
             Some(Commands::Request { channel, data, header, count, translate }) => {
-                let mq = Q::connect(url_or_empty(&args.url)).await?;
+                let mq = Q::connect(url_or_empty(&args.url), &args.options).await?;
                 let data = data_or_stdin(data)?;
                 let mut idx = 0;
                 for _ in 0..count {
@@ -318,9 +426,12 @@ pub async fn run<Q: MessageQueue>(args: impl Iterator<Item = String>) {
                     print_data(idx, &frame, &translate).await?;
                 }
             }
+            Some(Commands::Backend(command)) => {
+                Q::command(url_or_empty(&args.url), &args.options, command).await?;
+            }
             None => {
                 use clap::CommandFactory;
-                let _ = BaseArgs::command().print_help();
+                let _ = BaseArgs::<Q::Options, Q::Commands, Q::PublishOptions, Q::SubscribeOptions>::command().print_help();
             }
         }
 
@@ -49,18 +49,57 @@ pub struct BaseArgs {
 #[derive(Parser, Debug)]
 #[command(subcommand_help_heading = "Transports", subcommand_value_name = "URL")]
 enum Transport {
+    #[cfg(feature = "backend-amqp")]
+    #[command(about = "amqp 0-9-1 (rabbitmq.com) client\ndefault: amqp://localhost:5672")]
+    Amqp,
     #[cfg(feature = "backend-centrifuge")]
     #[command(about = "centrifuge (centrifugal.dev) client, json encoding\ndefault: cfj+ws://localhost:8000/connection/websocket")]
     Cfj,
     #[cfg(feature = "backend-centrifuge")]
     #[command(about = "centrifuge (centrifugal.dev) client, protobuf encoding\ndefault: cfp+ws://localhost:8000/connection/websocket?format=protobuf")]
     Cfp,
+    #[cfg(feature = "backend-centrifuge")]
+    #[command(about = "centrifuge (centrifugal.dev) server http api client\ndefault: cfapi+http://localhost:8000/api")]
+    Cfapi,
+    #[cfg(feature = "backend-http")]
+    #[command(about = "http client, pub/req send the message as request body, channel is appended to url path\ndefault: http://localhost:8080")]
+    Http,
+    #[cfg(feature = "backend-kafka")]
+    #[command(about = "kafka (kafka.apache.org) client\ndefault: kafka+localhost:9092")]
+    Kafka,
+    #[cfg(feature = "backend-mqttsn")]
+    #[command(about = "mqtt-sn 1.2 client over udp, talks to an mqtt-sn gateway\ndefault: mqttsn+udp://localhost:1884")]
+    Mqttsn,
     #[cfg(feature = "backend-nats")]
     #[command(about = "nats (nats.io) client\ndefault: nats://localhost:4222")]
     Nats,
+    #[cfg(feature = "backend-postgres")]
+    #[command(about = "postgresql (postgresql.org) LISTEN/NOTIFY client\ndefault: postgres://localhost:5432")]
+    Postgres,
+    #[cfg(feature = "backend-redis")]
+    #[command(about = "redis (redis.io) pub/sub client, use redis+stream:// for streams\ndefault: redis://localhost:6379")]
+    Redis,
+    #[cfg(feature = "backend-http")]
+    #[command(about = "server-sent events client, channel is appended to url path\ndefault: sse+http://localhost:8080")]
+    Sse,
+    #[cfg(feature = "backend-stomp")]
+    #[command(about = "stomp 1.2 (stomp.github.io) client for ActiveMQ, Artemis, RabbitMQ\ndefault: stomp://localhost:61613")]
+    Stomp,
+    #[cfg(feature = "backend-udp")]
+    #[command(about = "udp datagrams, unicast, broadcast or multicast group\ndefault: udp://localhost:5000")]
+    Udp,
+    #[cfg(feature = "backend-udp")]
+    #[command(about = "unix domain datagram socket\ndefault: unixgram:///tmp/mqcat.sock")]
+    Unixgram,
+    #[cfg(feature = "backend-ws")]
+    #[command(about = "raw websocket client, channel is appended to url path\ndefault: ws://localhost:8080")]
+    Ws,
     #[cfg(feature = "backend-zenoh")]
     #[command(about = "zenoh (zenoh.io) client\ndefault: zenoh+tcp://localhost:7558")]
     Zenoh,
+    #[cfg(feature = "backend-zmq")]
+    #[command(about = "zeromq (zeromq.org) PUB/SUB/REQ sockets, add ?bind or ?connect to url to override defaults\ndefault: zmq+tcp://localhost:5555")]
+    Zmq,
 }
 
 #[tokio::main]
@@ -103,6 +142,10 @@ async fn main() {
     args[transport_idx] = url;
 
     match &*transport {
+        #[cfg(feature = "backend-amqp")]
+        "amqp" | "amqps" => {
+            mqcat::backends::amqp::run(args.into_iter()).await;
+        }
         #[cfg(feature = "backend-centrifuge")]
         "cfj" => {
             mqcat::backends::centrifuge::run::<true>(args.into_iter()).await;
@@ -111,24 +154,110 @@ async fn main() {
         "cfp" => {
             mqcat::backends::centrifuge::run::<false>(args.into_iter()).await;
         }
+        #[cfg(feature = "backend-centrifuge")]
+        "cfapi" => {
+            mqcat::backends::cfapi::run(args.into_iter()).await;
+        }
+        #[cfg(feature = "backend-http")]
+        "http" | "https" => {
+            mqcat::backends::http::run(args.into_iter()).await;
+        }
+        #[cfg(feature = "backend-kafka")]
+        "kafka" => {
+            mqcat::backends::kafka::run(args.into_iter()).await;
+        }
+        #[cfg(feature = "backend-mqttsn")]
+        "mqttsn" => {
+            mqcat::backends::mqttsn::run(args.into_iter()).await;
+        }
         #[cfg(feature = "backend-nats")]
         "nats" => {
             mqcat::backends::nats::run(args.into_iter()).await;
         }
+        #[cfg(feature = "backend-postgres")]
+        "postgres" | "postgresql" => {
+            mqcat::backends::postgres::run(args.into_iter()).await;
+        }
+        #[cfg(feature = "backend-redis")]
+        "redis" | "rediss" => {
+            // redis+stream://host and rediss+stream://host select stream mode for redis://host
+            let url = &args[transport_idx];
+            let stream_url = match url.strip_prefix("stream://") {
+                Some(host) => Some(format!("{}://{}", transport, host)),
+                None if url == "stream" => Some(String::new()),
+                None => None,
+            };
+            if let Some(url) = stream_url {
+                args[transport_idx] = url;
+                mqcat::backends::redis::run::<true>(args.into_iter()).await;
+            } else {
+                mqcat::backends::redis::run::<false>(args.into_iter()).await;
+            }
+        }
+        #[cfg(feature = "backend-http")]
+        "sse" => {
+            mqcat::backends::sse::run(args.into_iter()).await;
+        }
+        #[cfg(feature = "backend-stomp")]
+        "stomp" => {
+            mqcat::backends::stomp::run(args.into_iter()).await;
+        }
+        #[cfg(feature = "backend-udp")]
+        "udp" => {
+            mqcat::backends::udp::run::<false>(args.into_iter()).await;
+        }
+        #[cfg(feature = "backend-udp")]
+        "unixgram" => {
+            mqcat::backends::udp::run::<true>(args.into_iter()).await;
+        }
+        #[cfg(feature = "backend-ws")]
+        "ws" | "wss" => {
+            mqcat::backends::ws::run(args.into_iter()).await;
+        }
         #[cfg(feature = "backend-zenoh")]
         "zenoh" => {
             mqcat::backends::zenoh::run(args.into_iter()).await;
         }
+        #[cfg(feature = "backend-zmq")]
+        "zmq" => {
+            mqcat::backends::zmq::run(args.into_iter()).await;
+        }
         _ => {
             let transports: Vec<&str> = vec![
+                #[cfg(feature = "backend-amqp")]
+                "amqp",
                 #[cfg(feature = "backend-centrifuge")]
                 "cfj",
                 #[cfg(feature = "backend-centrifuge")]
                 "cfp",
+                #[cfg(feature = "backend-centrifuge")]
+                "cfapi",
+                #[cfg(feature = "backend-http")]
+                "http",
+                #[cfg(feature = "backend-kafka")]
+                "kafka",
+                #[cfg(feature = "backend-mqttsn")]
+                "mqttsn",
                 #[cfg(feature = "backend-nats")]
                 "nats",
+                #[cfg(feature = "backend-postgres")]
+                "postgres",
+                #[cfg(feature = "backend-redis")]
+                "redis",
+                #[cfg(feature = "backend-http")]
+                "sse",
+                #[cfg(feature = "backend-stomp")]
+                "stomp",
+                #[cfg(feature = "backend-udp")]
+                "udp",
+                #[cfg(feature = "backend-udp")]
+                "unixgram",
+                #[cfg(feature = "backend-ws")]
+                "ws",
                 #[cfg(feature = "backend-zenoh")]
                 "zenoh",
+                #[cfg(feature = "backend-zmq")]
+                "zmq",
             ];
 
             BaseArgs::command().error(
@@ -4,10 +4,59 @@ pub struct Frame {
     pub payload: Vec<u8>,
 }
 
+/// Connection state changes of backends that reconnect in the background.
+#[derive(Clone, Debug)]
+pub enum ConnectionEvent {
+    Connected,
+    Disconnected { code: Option<u32>, reason: String },
+    ReconnectFailed { error: String },
+}
+
 pub trait MessageQueue {
-    fn connect(addr: Option<&str>) -> impl Future<Output = anyhow::Result<Self>> where Self: Sized;
-    fn info(&self) -> impl Future<Output = anyhow::Result<String>>;
+    /// backend-specific command line options, shared by all commands
+    type Options: clap::Args + std::fmt::Debug;
+    /// backend-specific commands, in addition to pub/sub/req
+    type Commands: clap::Subcommand + std::fmt::Debug;
+    /// backend-specific options of `pub`, passed to `publisher`
+    type PublishOptions: clap::Args + std::fmt::Debug;
+    /// backend-specific options of `sub`, passed to `subscribe`
+    type SubscribeOptions: clap::Args + std::fmt::Debug;
+    /// publisher handle returned by `publisher`, reused for every message sent to its topic
+    type Publisher<'a>: Publisher where Self: 'a;
+
+    fn connect(addr: Option<&str>, options: &Self::Options) -> impl Future<Output = anyhow::Result<Self>> where Self: Sized;
+    fn info(&self, json: bool) -> impl Future<Output = anyhow::Result<String>>;
     fn publish(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> impl Future<Output = anyhow::Result<()>>;
-    fn subscribe(&self, topic: &str) -> impl futures_util::Stream<Item = anyhow::Result<Frame>>;
+    fn publisher(&self, topic: &str, options: &Self::PublishOptions) -> impl Future<Output = anyhow::Result<Self::Publisher<'_>>>;
+    fn subscribe(&self, topic: &str, options: &Self::SubscribeOptions) -> impl futures_util::Stream<Item = anyhow::Result<Frame>>;
     fn request(&self, topic: &str, headers: &[(String, String)], payload: &[u8]) -> impl Future<Output = anyhow::Result<Frame>>;
+    fn command(addr: Option<&str>, options: &Self::Options, command: Self::Commands) -> impl Future<Output = anyhow::Result<()>>;
+
+    /// connection state changes, used by `sub --reconnect` to report gaps in the output
+    fn connection_events(&self) -> impl futures_util::Stream<Item = ConnectionEvent> {
+        futures_util::stream::pending()
+    }
+}
+
+pub trait Publisher {
+    fn publish(&self, headers: &[(String, String)], payload: &[u8]) -> impl Future<Output = anyhow::Result<()>>;
+}
+
+/// Publisher for backends that don't have to declare anything before publishing,
+/// it simply forwards every message to `MessageQueue::publish`.
+pub struct TopicPublisher<'a, Q> {
+    mq: &'a Q,
+    topic: String,
+}
+
+impl<'a, Q: MessageQueue> TopicPublisher<'a, Q> {
+    pub fn new(mq: &'a Q, topic: &str) -> Self {
+        Self { mq, topic: topic.to_owned() }
+    }
+}
+
+impl<Q: MessageQueue> Publisher for TopicPublisher<'_, Q> {
+    async fn publish(&self, headers: &[(String, String)], payload: &[u8]) -> anyhow::Result<()> {
+        self.mq.publish(&self.topic, headers, payload).await
+    }
 }
@@ -10,3 +10,132 @@ pub fn format_table(info: &[(&str, String)]) -> String {
     }
     table
 }
+
+pub fn info_to_json(info: &[(&str, String)]) -> serde_json::Map<String, serde_json::Value> {
+    // same layout as `format_table`, keys that occur more than once are collected into arrays
+    let mut object = serde_json::Map::new();
+    for (key, value) in info {
+        if key.is_empty() {
+            continue;
+        }
+        let value = serde_json::Value::String(value.clone());
+        match object.get_mut(*key) {
+            Some(serde_json::Value::Array(values)) => values.push(value),
+            Some(existing) => *existing = serde_json::Value::Array(vec![existing.take(), value]),
+            None => { object.insert(key.to_string(), value); }
+        }
+    }
+    object
+}
+
+pub fn format_json(info: &[(&str, String)]) -> String {
+    let mut json = serde_json::to_string_pretty(&info_to_json(info)).unwrap_or_default();
+    json.push('\n');
+    json
+}
+
+pub fn format_info(info: &[(&str, String)], json: bool) -> String {
+    if json {
+        format_json(info)
+    } else {
+        format_table(info)
+    }
+}
+
+/// Stream of broadcast channel messages, skipping the ones that were missed by a slow receiver.
+pub fn broadcast_stream<T: Clone>(mut receiver: tokio::sync::broadcast::Receiver<T>) -> impl futures_util::Stream<Item = T> {
+    async_stream::stream! {
+        loop {
+            match receiver.recv().await {
+                Ok(item) => yield item,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+/// Channel is appended to url path for transports that address channels by url, empty channel keeps the url as is.
+pub fn topic_url(base: &str, topic: &str) -> String {
+    let topic = topic.trim_start_matches('/');
+    if topic.is_empty() {
+        return base.to_owned();
+    }
+    let (base, query) = match base.split_once('?') {
+        Some((base, query)) => (base, Some(query)),
+        None => (base, None),
+    };
+    let mut url = format!("{}/{}", base.trim_end_matches('/'), topic);
+    if let Some(query) = query {
+        url.push('?');
+        url.push_str(query);
+    }
+    url
+}
+
+/// Path part of url, used as channel name of received frames.
+pub fn url_path(url: &str) -> &str {
+    let rest = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let rest = rest.split('?').next().unwrap_or_default();
+    rest.find('/').map(|pos| &rest[pos..]).unwrap_or("/")
+}
+
+/// Request received by `http_stand_in`, header lines are lowercased.
+#[cfg(test)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: String,
+    pub body: Vec<u8>,
+}
+
+/// Accepts a single HTTP request and sends `response` as is, returns server address and the request.
+#[cfg(test)]
+pub fn http_stand_in(response: String) -> (std::net::SocketAddr, std::thread::JoinHandle<HttpRequest>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut headers = String::new();
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((key, value)) = line.split_once(':') && key.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap();
+            }
+            headers.push_str(&line.to_ascii_lowercase());
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        stream.write_all(response.as_bytes()).unwrap();
+        let mut parts = request_line.split(' ');
+        let method = parts.next().unwrap().to_owned();
+        let path = parts.next().unwrap().to_owned();
+        HttpRequest { method, path, headers, body }
+    });
+    (addr, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topic_urls() {
+        assert_eq!(topic_url("ws://localhost:8080", "feed"), "ws://localhost:8080/feed");
+        assert_eq!(topic_url("ws://localhost:8080/api/", "/feed"), "ws://localhost:8080/api/feed");
+        assert_eq!(topic_url("wss://example.com/api?token=x", "feed"), "wss://example.com/api/feed?token=x");
+        assert_eq!(topic_url("ws://localhost:8080/feed", ""), "ws://localhost:8080/feed");
+        assert_eq!(url_path("ws://localhost:8080/api/feed?token=x"), "/api/feed");
+        assert_eq!(url_path("ws://localhost:8080"), "/");
+    }
+}